@@ -0,0 +1,38 @@
+use futures_util::StreamExt;
+use log::{error, info};
+use rustls_acme::{axum::AxumAcceptor, caches::DirCache, AcmeConfig};
+use std::sync::Arc;
+
+/// Where certificates and the ACME account key are kept when ACME_CACHE_DIR is unset.
+const DEFAULT_CACHE_DIR: &str = "acme";
+
+/// Obtains and renews certificates for the comma separated `domains` from
+/// Let's Encrypt. Challenges are answered with TLS-ALPN-01 on the HTTPS
+/// listener itself, so nothing has to listen on port 80. Like the static
+/// certificate listener it offers HTTP/2 via ALPN, so the many small requests
+/// a listing triggers multiplex over one connection.
+pub(crate) fn acceptor(
+    domains: &str,
+    contact: Option<String>,
+    cache_dir: Option<String>,
+    staging: bool,
+) -> AxumAcceptor {
+    let domains: Vec<&str> = domains.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()).collect();
+    let mut state = AcmeConfig::new(domains)
+        .contact(contact.iter().map(|email| format!("mailto:{email}")))
+        .cache(DirCache::new(cache_dir.unwrap_or_else(|| String::from(DEFAULT_CACHE_DIR))))
+        .directory_lets_encrypt(!staging)
+        .state();
+    let mut tls = (*state.default_rustls_config()).clone();
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = state.axum_acceptor(Arc::new(tls));
+    tokio::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => info!("ACME: {event:?}"),
+                Err(e) => error!("ACME: {e}"),
+            }
+        }
+    });
+    acceptor
+}
@@ -0,0 +1,74 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use log::{info, warn};
+use serde_json::json;
+
+use crate::{auth::AuthenticatedUser, status, wants_html, Context};
+
+macro_rules! require_admin {
+    ($user:expr) => {
+        if !$user.admin {
+            warn!("403 {} is not an admin", $user.username);
+            return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+        }
+    };
+}
+
+pub(crate) async fn list_transfers(
+    State(ctx): State<Context>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Response {
+    require_admin!(user);
+    Json(ctx.transfers.list()).into_response()
+}
+
+pub(crate) async fn cancel_transfer(
+    State(ctx): State<Context>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(id): Path<u64>,
+) -> Response {
+    require_admin!(user);
+    if ctx.transfers.cancel(id) {
+        info!("AUDIT {} cancelled transfer {id}", user.username);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (StatusCode::NOT_FOUND, "Not Found").into_response()
+    }
+}
+
+pub(crate) async fn usage(
+    State(ctx): State<Context>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Response {
+    require_admin!(user);
+    Json(ctx.usage.snapshot()).into_response()
+}
+
+/// Uptime, active connections and transfers, recent errors and cache hit
+/// rates; HTML for browsers, JSON otherwise.
+pub(crate) async fn status(
+    State(ctx): State<Context>,
+    Extension(user): Extension<AuthenticatedUser>,
+    headers: HeaderMap,
+) -> Response {
+    require_admin!(user);
+    let mut summary = ctx.status.summary();
+    summary["active_transfers"] = json!(ctx.transfers.list().len());
+    summary["caches"] = json!({
+        "etag_hashes": ctx.etags.stats().summary(),
+        "previews": ctx.previews.as_ref().map(|p| p.stats().summary()),
+    });
+    if wants_html(&headers) {
+        (
+            [(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))],
+            status::html(&summary),
+        )
+            .into_response()
+    } else {
+        Json(summary).into_response()
+    }
+}
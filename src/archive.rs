@@ -0,0 +1,254 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use flate2::read::GzDecoder;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+use tokio::io::DuplexStream;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+
+use crate::feed::xml_escape;
+use crate::{content_disposition, is_safe, paths};
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// A path that points into an archive, e.g. `/dl/photos.zip/2024/a.jpg`.
+pub(crate) struct ArchivePath {
+    archive: PathBuf,
+    kind: Kind,
+    member: String,
+}
+
+pub(crate) enum Entry {
+    Dir(Vec<String>),
+    /// A member and its uncompressed size, from the archive's headers.
+    File(u64),
+}
+
+/// Finds the archive file that `file_path` reaches into, if any. Only called
+/// for paths that don't exist on disk, so the archive is always an ancestor
+/// (or the path itself, when requested with a trailing slash).
+pub(crate) fn locate(file_path: &Path, base_dir: &str) -> Option<ArchivePath> {
+    let file_path: PathBuf = file_path.components().collect();
+    for candidate in file_path.ancestors() {
+        if !candidate.starts_with(base_dir) {
+            break;
+        }
+        if !candidate.is_file() {
+            continue;
+        }
+        let kind = kind(candidate)?;
+        if !is_safe(&candidate.to_path_buf(), base_dir) {
+            return None;
+        }
+        let member: Vec<&str> = file_path
+            .strip_prefix(candidate)
+            .ok()?
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<_>>()?;
+        return Some(ArchivePath {
+            archive: candidate.to_path_buf(),
+            kind,
+            member: member.join("/"),
+        });
+    }
+    None
+}
+
+impl ArchivePath {
+    pub(crate) fn file(&self) -> &Path {
+        &self.archive
+    }
+
+    pub(crate) fn member(&self) -> &str {
+        &self.member
+    }
+
+    /// Looks the member up in the archive index. Directories need not have
+    /// their own entry; any member below the prefix makes it one.
+    pub(crate) fn entry(&self) -> io::Result<Option<Entry>> {
+        let members = list_members(&self.archive, self.kind)?;
+        if let (false, Some(size)) = (self.member.is_empty(), members.get(&self.member)) {
+            return Ok(Some(Entry::File(*size)));
+        }
+        let prefix = if self.member.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.member)
+        };
+        let children: BTreeSet<String> = members
+            .keys()
+            .filter_map(|n| n.strip_prefix(&prefix))
+            .filter_map(|rest| rest.split('/').next())
+            .filter(|c| !c.is_empty())
+            .map(String::from)
+            .collect();
+        if children.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Entry::Dir(children.into_iter().collect())))
+        }
+    }
+
+    /// Streams a single member. Decompression runs on the blocking pool and is
+    /// piped into the response, so the member is never buffered whole.
+    pub(crate) fn stream(&self) -> ReaderStream<DuplexStream> {
+        let (reader, writer) = tokio::io::duplex(64 * 1024);
+        let mut writer = SyncIoBridge::new(writer);
+        let archive = self.archive.clone();
+        let kind = self.kind;
+        let member = self.member.clone();
+        tokio::task::spawn_blocking(move || {
+            //the response is already under way; ending it short of its Content-Length
+            //is what tells the client (and the transfer log) it's incomplete
+            if let Err(e) = copy_member(&archive, kind, &member, &mut writer) {
+                log::warn!("Failed to read {member} from {}: {e}", archive.display());
+            }
+        });
+        ReaderStream::new(reader)
+    }
+}
+
+fn open_tar(path: &Path, kind: Kind) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let f = File::open(path)?;
+    let reader: Box<dyn Read> = match kind {
+        Kind::TarGz => Box::new(GzDecoder::new(f)),
+        _ => Box::new(f),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Normalizes member names so `./a/b/` and `a/b` compare equal. Members that
+/// climb out of the archive root are dropped. Names that aren't UTF-8 are
+/// converted lossily, the same way their links are requested.
+fn normalize(name: &Path) -> Option<String> {
+    let mut parts = vec![];
+    for c in name.components() {
+        match c {
+            Component::Normal(p) => parts.push(p.to_string_lossy()),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("/"))
+    }
+}
+
+/// Member names and their sizes. If a name occurs twice the first one counts,
+/// as it's the one `copy_member` finds.
+fn list_members(path: &Path, kind: Kind) -> io::Result<BTreeMap<String, u64>> {
+    let mut members = BTreeMap::new();
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i).map_err(io::Error::other)?;
+                //directory entries end in "/" and are implied by their members anyway
+                if file.is_file() {
+                    if let Some(name) = normalize(Path::new(file.name())) {
+                        members.entry(name).or_insert(file.size());
+                    }
+                }
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            for entry in open_tar(path, kind)?.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    if let Some(name) = normalize(&entry.path()?) {
+                        members.entry(name).or_insert(entry.size());
+                    }
+                }
+            }
+        }
+    }
+    Ok(members)
+}
+
+fn copy_member(path: &Path, kind: Kind, member: &str, out: &mut impl Write) -> io::Result<()> {
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i).map_err(io::Error::other)?;
+                if file.is_file() && normalize(Path::new(file.name())).as_deref() == Some(member) {
+                    io::copy(&mut file, out)?;
+                    return Ok(());
+                }
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            for entry in open_tar(path, kind)?.entries()? {
+                let mut entry = entry?;
+                if normalize(&entry.path()?).as_deref() == Some(member) {
+                    io::copy(&mut entry, out)?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("{member} vanished from archive")))
+}
+
+/// Lists the members directly below `requested`, the URL path of the archive
+/// or a directory inside it. Links are prefixed with `base_path`.
+pub(crate) fn listing(children: &[String], requested: &Path, base_path: &str) -> Response<Body> {
+    let href_base = format!("{base_path}{}", paths::encode(requested));
+    let base = requested.to_string_lossy();
+    let parent = href_base.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+    let mut r = format!("<a href=\"{parent}/\">..</a><br>\n");
+    for c in children {
+        let href = format!("{href_base}/{}", paths::encode(Path::new(c)));
+        r.push_str(&format!("<a href=\"{href}\">{}</a><br>\n", xml_escape(&format!("{base}/{c}"))));
+    }
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))
+        .body(Body::from(r))
+        .unwrap()
+}
+
+pub(crate) fn member_response(body: Body, member: &str, len: u64) -> Response<Body> {
+    let filetype = mime_guess::from_path(member).first_or_octet_stream();
+    let filename = member.rsplit('/').next().unwrap_or("file");
+    Response::builder()
+        .status(200)
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(filetype.essence_str()).unwrap(),
+        )
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("attachment", filename),
+        )
+        .header(header::CONTENT_LENGTH, len)
+        .body(body)
+        .unwrap()
+}
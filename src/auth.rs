@@ -1,30 +1,229 @@
 use axum::extract::State;
-use axum::http::HeaderValue;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::IntoResponse;
 use axum::{extract::Request, http::header, middleware::Next, response::Response};
 use base64::Engine as _;
+use log::warn;
 use base64::engine::general_purpose::STANDARD as base64_engine;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde_json::{Map, Value};
 use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::config::Report;
+use crate::error_pages::UserErrorPages;
+use crate::provision::{self, Ownership};
+use crate::schedule::Schedule;
+
 #[derive(Deserialize)]
 pub(crate) struct UserData {
     password: String,
     directory: String,
+    schedule: Option<Schedule>,
+    #[serde(default)]
+    admin: bool,
+    max_transfers: Option<usize>,
+    monthly_transfer_limit: Option<u64>,
+    skeleton: Option<String>,
+    #[serde(flatten)]
+    ownership: Ownership,
+    #[serde(default)]
+    clearance: Vec<String>,
+    error_pages: Option<String>,
 }
 
 #[derive(Clone)]
 pub(crate) struct AuthenticatedUser {
     pub(crate) username: String,
     pub(crate) directory: String,
+    pub(crate) admin: bool,
+    pub(crate) max_transfers: Option<usize>,
+    /// Bytes this user may download per calendar month.
+    pub(crate) monthly_transfer_limit: Option<u64>,
+    /// Copied into the directory when it is created on first login.
+    pub(crate) skeleton: Option<String>,
+    pub(crate) ownership: Ownership,
+    /// Sensitivity labels this user may open, see LABEL_XATTR.
+    pub(crate) clearance: Vec<String>,
+    /// Overrides ERROR_PAGES_DIR for this user's error responses.
+    pub(crate) error_pages: Option<String>,
+    /// Set when an admin is acting as this user via the impersonation header.
+    pub(crate) impersonated_by: Option<String>,
 }
 
+/// Lets an admin see exactly what another user sees, for debugging access reports.
+const IMPERSONATE_HEADER: &str = "X-Impersonate-User";
+
 pub(crate) type Users = Arc<HashMap<String, UserData>>;
 
+/// Every key a user entry may have; anything else is reported by `check_users`.
+const USER_FIELDS: &[&str] = &[
+    "password",
+    "directory",
+    "schedule",
+    "admin",
+    "max_transfers",
+    "monthly_transfer_limit",
+    "skeleton",
+    "directory_mode",
+    "file_mode",
+    "uid",
+    "gid",
+    "groups",
+    "clearance",
+    "error_pages",
+];
+
+/// Lowest bcrypt cost accepted; below this hashes are cheap to brute force.
+const MIN_BCRYPT_COST: u32 = 10;
+
+fn check_field<T: DeserializeOwned>(value: &Value) -> Result<T, String> {
+    serde_json::from_value(value.clone()).map_err(|e| e.to_string())
+}
+
+/// The cost of a bcrypt hash, or `None` if it isn't one.
+fn bcrypt_cost(hash: &str) -> Option<u32> {
+    let mut parts = hash.split('$');
+    let (Some(""), Some(version), Some(cost), Some(rest)) = (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if !["2a", "2b", "2x", "2y"].contains(&version) || rest.len() != 53 {
+        return None;
+    }
+    cost.parse().ok()
+}
+
+/// Validates the users file, reporting every problem found with the user and
+/// field it belongs to (or the line and column for JSON syntax errors).
+/// Missing directories are fine when they will be created on first login.
+/// A weak bcrypt cost or a missing directory only affects that one user, so
+/// they're warnings.
+pub(crate) fn check_users(path: &str, auto_create: bool) -> Report {
+    let mut report = Report::default();
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => {
+            report.errors.push(format!("{path}: {e}"));
+            return report;
+        }
+    };
+    let users: Map<String, Value> = match serde_json::from_str(&data) {
+        Ok(u) => u,
+        Err(e) => {
+            report.errors.push(format!("{path}: invalid JSON: {e}"));
+            return report;
+        }
+    };
+    for (name, entry) in &users {
+        let mut problem = |field: &str, message: String| report.errors.push(format!("{path}: user \"{name}\": {field}: {message}"));
+        let mut warning = |field: &str, message: String| report.warnings.push(format!("{path}: user \"{name}\": {field}: {message}"));
+        let Some(fields) = entry.as_object() else {
+            problem("entry", String::from("expected an object"));
+            continue;
+        };
+        for key in fields.keys() {
+            if !USER_FIELDS.contains(&key.as_str()) {
+                problem(key, String::from("unknown field"));
+            }
+        }
+        match fields.get("password").map(check_field::<String>) {
+            None => problem("password", String::from("missing")),
+            Some(Err(e)) => problem("password", e),
+            Some(Ok(hash)) => match bcrypt_cost(&hash) {
+                None => problem("password", String::from("not a bcrypt hash (plaintext?)")),
+                Some(cost) if cost < MIN_BCRYPT_COST => {
+                    warning("password", format!("bcrypt cost {cost} is weak, use at least {MIN_BCRYPT_COST}"))
+                }
+                Some(_) => {}
+            },
+        }
+        match fields.get("directory").map(check_field::<String>) {
+            None => problem("directory", String::from("missing")),
+            Some(Err(e)) => problem("directory", e),
+            Some(Ok(dir)) => match expand_directory(&dir, name) {
+                Err(e) => problem("directory", e),
+                Ok(dir) => {
+                    if !Path::new(&dir).is_absolute() {
+                        problem("directory", format!("{dir} is not an absolute path"));
+                    } else if Path::new(&dir).exists() && !Path::new(&dir).is_dir() {
+                        problem("directory", format!("{dir} is not a directory"));
+                    } else if !Path::new(&dir).exists() && !auto_create {
+                        warning("directory", format!("{dir} does not exist"));
+                    }
+                }
+            },
+        }
+        if let Some(Err(e)) = fields.get("schedule").map(check_field::<Schedule>) {
+            problem("schedule", e);
+        }
+        if let Some(Err(e)) = fields.get("admin").map(check_field::<bool>) {
+            problem("admin", e);
+        }
+        if let Some(Err(e)) = fields.get("max_transfers").map(check_field::<usize>) {
+            problem("max_transfers", e);
+        }
+        if let Some(Err(e)) = fields.get("monthly_transfer_limit").map(check_field::<u64>) {
+            problem("monthly_transfer_limit", e);
+        }
+        for field in ["skeleton", "error_pages"] {
+            match fields.get(field).map(check_field::<String>) {
+                Some(Err(e)) => problem(field, e),
+                Some(Ok(dir)) if !Path::new(&dir).is_dir() => problem(field, format!("{dir} is not a directory")),
+                _ => {}
+            }
+        }
+        for field in ["directory_mode", "file_mode"] {
+            match fields.get(field).map(check_field::<String>) {
+                Some(Err(e)) => problem(field, e),
+                Some(Ok(mode)) if provision::parse_mode(&mode).is_none() => {
+                    problem(field, format!("{mode} is not an octal permission mode"))
+                }
+                _ => {}
+            }
+        }
+        for field in ["uid", "gid"] {
+            if let Some(Err(e)) = fields.get(field).map(check_field::<u32>) {
+                problem(field, e);
+            }
+        }
+        if let Some(Err(e)) = fields.get("groups").map(check_field::<Vec<u32>>) {
+            problem("groups", e);
+        }
+        if let Some(Err(e)) = fields.get("clearance").map(check_field::<Vec<String>>) {
+            problem("clearance", e);
+        }
+    }
+    report
+}
+
+/// Expands `${VAR}` from the environment and `{username}` in a directory, so
+/// one users file works across hosts, e.g. `${DATA_ROOT}/{username}`.
+fn expand_directory(dir: &str, username: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = dir;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| format!("unterminated ${{ in {dir}"))?;
+        let name = &after[..end];
+        expanded.push_str(&env::var(name).map_err(|_| format!("environment variable {name} is not set"))?);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded.replace("{username}", username))
+}
+
 pub(crate) fn load_users(path: &str) -> Users {
     let data = fs::read_to_string(path).expect("Failed to read json");
-    let map: HashMap<String, UserData> = serde_json::from_str(&data).expect("Invalid JSON format");
+    let mut map: HashMap<String, UserData> = serde_json::from_str(&data).expect("Invalid JSON format");
+    for (name, user) in map.iter_mut() {
+        user.directory = expand_directory(&user.directory, name).expect("Invalid directory in users json");
+    }
     Arc::new(map)
 }
 
@@ -41,12 +240,40 @@ pub(crate) async fn basic_auth(State(users): State<Users>, mut req: Request, nex
                         if let Some(user) = users.get(username) {
 
                             if bcrypt::verify(password, &user.password).unwrap_or(false) {
-                                let au = AuthenticatedUser {
-                                    username: String::from(username),
-                                    directory: user.directory.clone()
+                                if let Some(schedule) = &user.schedule {
+                                    if !schedule.allows_now() {
+                                        warn!("403 {username} denied: outside allowed access schedule");
+                                        return Response::builder()
+                                            .status(403)
+                                            .body("Forbidden".into())
+                                            .unwrap();
+                                    }
+                                }
+                                let au = match req.headers().get(IMPERSONATE_HEADER) {
+                                    Some(target) => match impersonate(&users, username, user, target) {
+                                        Ok(au) => au,
+                                        Err(rejection) => return rejection.into_response(),
+                                    },
+                                    None => AuthenticatedUser {
+                                        username: String::from(username),
+                                        directory: user.directory.clone(),
+                                        admin: user.admin,
+                                        max_transfers: user.max_transfers,
+                                        monthly_transfer_limit: user.monthly_transfer_limit,
+                                        skeleton: user.skeleton.clone(),
+                                        ownership: user.ownership.clone(),
+                                        clearance: user.clearance.clone(),
+                                        error_pages: user.error_pages.clone(),
+                                        impersonated_by: None,
+                                    },
                                 };
+                                let error_pages = au.error_pages.clone().map(PathBuf::from);
                                 req.extensions_mut().insert(au);
-                                return next.run(req).await;
+                                let mut response = next.run(req).await;
+                                if let Some(dir) = error_pages {
+                                    response.extensions_mut().insert(UserErrorPages(dir));
+                                }
+                                return response;
                             }
                         }
                     }
@@ -63,4 +290,38 @@ pub(crate) async fn basic_auth(State(users): State<Users>, mut req: Request, nex
         )
         .body("Unauthorized".into())
         .unwrap()
-}
\ No newline at end of file
+}
+
+fn impersonate(
+    users: &Users,
+    username: &str,
+    user: &UserData,
+    target: &HeaderValue,
+) -> Result<AuthenticatedUser, (StatusCode, &'static str)> {
+    let target = target.to_str().unwrap_or("");
+    if !user.admin {
+        warn!("AUDIT 403 {username} attempted to impersonate {target} without admin rights");
+        return Err((StatusCode::FORBIDDEN, "Forbidden"));
+    }
+    match users.get(target) {
+        Some(target_user) => {
+            warn!("AUDIT {username} is impersonating {target}");
+            Ok(AuthenticatedUser {
+                username: String::from(target),
+                directory: target_user.directory.clone(),
+                admin: target_user.admin,
+                max_transfers: target_user.max_transfers,
+                monthly_transfer_limit: target_user.monthly_transfer_limit,
+                skeleton: target_user.skeleton.clone(),
+                ownership: target_user.ownership.clone(),
+                clearance: target_user.clearance.clone(),
+                error_pages: target_user.error_pages.clone(),
+                impersonated_by: Some(String::from(username)),
+            })
+        }
+        None => {
+            warn!("AUDIT 400 {username} attempted to impersonate unknown user {target}");
+            Err((StatusCode::BAD_REQUEST, "Unknown user to impersonate"))
+        }
+    }
+}
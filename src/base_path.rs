@@ -0,0 +1,57 @@
+use axum::extract::{Request, State};
+use axum::http::{header, uri::PathAndQuery, StatusCode, Uri};
+use axum::{
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+/// BASE_PATH normalized to `/prefix` without a trailing slash, or empty when
+/// the server is mounted at the root.
+pub(crate) fn from_env(base_path: Option<String>) -> String {
+    let base_path = base_path.unwrap_or_default();
+    let trimmed = base_path.trim_matches('/');
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("/{trimmed}")
+    }
+}
+
+pub(crate) fn check(base_path: &str) -> Option<String> {
+    if !base_path.is_empty() && !base_path.starts_with('/') {
+        return Some(format!("BASE_PATH: {base_path} must start with /"));
+    }
+    if base_path.contains("//") || base_path.contains(['?', '#', '%', ' ']) {
+        return Some(format!("BASE_PATH: {base_path} must be a plain path like /storage"));
+    }
+    None
+}
+
+/// Strips the base path before routing, so the routes and handlers stay
+/// written against `/`. Anything outside it is a 404, and the bare prefix
+/// redirects to `prefix/` so relative links resolve against the root listing.
+pub(crate) async fn strip(State(base_path): State<Arc<str>>, mut req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    let Some(rest) = path.strip_prefix(&*base_path) else {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    };
+    if rest.is_empty() {
+        let location = match req.uri().query() {
+            Some(q) => format!("{base_path}/?{q}"),
+            None => format!("{base_path}/"),
+        };
+        return (StatusCode::MOVED_PERMANENTLY, [(header::LOCATION, location)]).into_response();
+    }
+    if !rest.starts_with('/') {
+        return (StatusCode::NOT_FOUND, "Not Found").into_response();
+    }
+    let stripped = match req.uri().query() {
+        Some(q) => format!("{rest}?{q}"),
+        None => String::from(rest),
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = PathAndQuery::try_from(stripped).ok();
+    *req.uri_mut() = Uri::from_parts(parts).unwrap();
+    next.run(req).await
+}
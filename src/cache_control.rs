@@ -0,0 +1,75 @@
+use axum::http::HeaderValue;
+use glob::Pattern;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct RawRule {
+    path: Option<String>,
+    #[serde(rename = "type")]
+    content_type: Option<String>,
+    cache_control: String,
+}
+
+/// `Cache-Control` for files whose request path and/or content type match,
+/// e.g. `immutable` for hashed assets or `no-store` for private documents.
+pub(crate) struct CacheRule {
+    path: Option<Pattern>,
+    content_type: Option<Pattern>,
+    value: HeaderValue,
+}
+
+pub(crate) type CacheRules = Arc<Vec<CacheRule>>;
+
+pub(crate) fn load_rules(path: &str) -> CacheRules {
+    let data = fs::read_to_string(path).expect("Failed to read cache control json");
+    let raw: Vec<RawRule> = serde_json::from_str(&data).expect("Invalid cache control JSON format");
+    let rules = raw
+        .into_iter()
+        .map(|r| CacheRule {
+            path: r.path.map(|p| Pattern::new(&p).expect("Invalid path glob in cache control")),
+            content_type: r.content_type.map(|t| Pattern::new(&t).expect("Invalid type glob in cache control")),
+            value: HeaderValue::from_str(&r.cache_control).expect("Invalid value in cache control"),
+        })
+        .collect();
+    Arc::new(rules)
+}
+
+pub(crate) fn check_rules(path: &str) -> Vec<String> {
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => return vec![format!("{path}: {e}")],
+    };
+    let raw: Vec<RawRule> = match serde_json::from_str(&data) {
+        Ok(r) => r,
+        Err(e) => return vec![format!("{path}: invalid JSON: {e}")],
+    };
+    let mut problems = vec![];
+    for (i, rule) in raw.iter().enumerate() {
+        if let Some(Err(e)) = rule.path.as_deref().map(Pattern::new) {
+            problems.push(format!("{path}: rule {i}: path: {e}"));
+        }
+        if let Some(Err(e)) = rule.content_type.as_deref().map(Pattern::new) {
+            problems.push(format!("{path}: rule {i}: type: {e}"));
+        }
+        if HeaderValue::from_str(&rule.cache_control).is_err() {
+            problems.push(format!("{path}: rule {i}: invalid cache_control value"));
+        }
+    }
+    problems
+}
+
+/// The value of the first rule matching the file; rules without a `path` or
+/// `type` match any.
+pub(crate) fn lookup(rules: &[CacheRule], request_path: &str, file_path: &Path) -> Option<HeaderValue> {
+    let filetype = mime_guess::from_path(file_path).first_or_octet_stream();
+    rules
+        .iter()
+        .find(|r| {
+            r.path.as_ref().is_none_or(|p| p.matches(request_path))
+                && r.content_type.as_ref().is_none_or(|t| t.matches(filetype.essence_str()))
+        })
+        .map(|r| r.value.clone())
+}
@@ -0,0 +1,45 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use std::path::{Path, PathBuf};
+
+/// Name of the per-directory checksum list, as written by `sha256sum * > SHA256SUMS`.
+const SUMS: &str = "SHA256SUMS";
+
+/// A checksum file that doesn't exist on disk but can be generated.
+pub(crate) enum Sidecar {
+    /// `file.iso.sha256` for `file.iso`.
+    File(PathBuf),
+    /// `SHA256SUMS` for every file in a directory.
+    Directory(PathBuf),
+}
+
+/// For a request of `file.iso.sha256` or `dir/SHA256SUMS` that doesn't exist
+/// on disk, what to checksum.
+pub(crate) fn sidecar(file_path: &Path) -> Option<Sidecar> {
+    if file_path.file_name().is_some_and(|n| n == SUMS) {
+        let dir = file_path.parent()?;
+        return dir.is_dir().then(|| Sidecar::Directory(dir.to_path_buf()));
+    }
+    let ext = file_path.extension()?.to_str()?;
+    if !ext.eq_ignore_ascii_case("sha256") {
+        return None;
+    }
+    let target = file_path.with_extension("");
+    target.is_file().then_some(Sidecar::File(target))
+}
+
+/// One line in the format `sha256sum -c` reads.
+pub(crate) fn line(hash: &str, path: &Path) -> String {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    format!("{hash}  {name}\n")
+}
+
+pub(crate) fn response(sums: String) -> Response<Body> {
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"))
+        .body(Body::from(sums))
+        .unwrap()
+}
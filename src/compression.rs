@@ -0,0 +1,49 @@
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use std::sync::Arc;
+use tower_http::compression::{
+    predicate::{Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+/// Content types compressed when COMPRESSION_TYPES is unset. Anything else
+/// (images, video, archives) is usually compressed already.
+const DEFAULT_TYPES: &str = "text/,application/json,application/javascript,application/xml,image/svg+xml";
+
+/// Responses smaller than this (bytes) are sent as-is when COMPRESSION_MIN_SIZE is unset.
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// gzip/brotli/zstd negotiated via `Accept-Encoding`, for responses whose
+/// Content-Type starts with one of the comma separated `types`. Files served
+/// from disk are left alone so ranges and validators keep meaning the same
+/// bytes; generated pages (listings, feeds, checksums) are compressed.
+pub(crate) fn layer(types: Option<String>, min_size: Option<String>) -> CompressionLayer<impl Predicate> {
+    let types: Arc<Vec<String>> = Arc::new(
+        types
+            .as_deref()
+            .unwrap_or(DEFAULT_TYPES)
+            .split(',')
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect(),
+    );
+    let min_size = min_size
+        .map(|m| m.parse().expect("Invalid Env var: COMPRESSION_MIN_SIZE"))
+        .unwrap_or(DEFAULT_MIN_SIZE);
+    let compressible = move |_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+        //a compressed byte range would no longer match its Content-Range, and a
+        //file's ETag and Accept-Ranges describe its bytes on disk: resuming a gzipped
+        //download with If-Range would append raw bytes to compressed ones
+        if [header::CONTENT_RANGE, header::ACCEPT_RANGES, header::ETAG]
+            .iter()
+            .any(|h| headers.contains_key(h))
+        {
+            return false;
+        }
+        let Some(content_type) = headers.get(header::CONTENT_TYPE).and_then(|t| t.to_str().ok()) else {
+            return false;
+        };
+        let content_type = content_type.to_lowercase();
+        types.iter().any(|t| content_type.starts_with(t.as_str()))
+    };
+    CompressionLayer::new().compress_when(SizeAbove::new(min_size).and(compressible))
+}
@@ -0,0 +1,195 @@
+use std::{env, path::Path};
+
+use crate::{auth, base_path, cache_control, cors, headers, provision, proxy, security, usage};
+
+/// What `check` found. `warnings` are problems the server has always started
+/// with, like a weak bcrypt cost or one user's missing directory: `--check`
+/// fails on them, a normal start only logs them.
+#[derive(Default)]
+pub(crate) struct Report {
+    pub(crate) errors: Vec<String>,
+    pub(crate) warnings: Vec<String>,
+}
+
+/// Checks the whole configuration up front and returns every problem found,
+/// so a broken deployment is reported in one go instead of panicking on the
+/// first bad value.
+pub(crate) fn check() -> Report {
+    let mut problems = vec![];
+    let mut warnings = vec![];
+
+    match env::var("USERS_JSON_PATH") {
+        Ok(path) => {
+            let auto_create = env::var("AUTO_CREATE_DIRECTORIES").is_ok_and(|v| v == "true");
+            let users = auth::check_users(&path, auto_create);
+            problems.extend(users.errors);
+            warnings.extend(users.warnings);
+        }
+        Err(_) => problems.push(String::from("USERS_JSON_PATH: missing")),
+    }
+    let unix_socket = env::var("LISTEN_UNIX_SOCKET").is_ok();
+    //HTTP_HOST/HTTP_PORT aren't bound when systemd passes the socket in
+    let bound_elsewhere = unix_socket || env::var("LISTEN_FDS").is_ok() || env::var("HTTP_LISTEN").is_ok();
+    if env::var("HTTP_HOST").is_err() && !bound_elsewhere {
+        problems.push(String::from("HTTP_HOST: missing"));
+    }
+    match env::var("HTTP_PORT") {
+        Ok(port) => {
+            if port.parse::<u16>().is_err() {
+                problems.push(format!("HTTP_PORT: {port} is not a valid port"));
+            }
+        }
+        Err(_) if !bound_elsewhere => problems.push(String::from("HTTP_PORT: missing")),
+        Err(_) => {}
+    }
+    if let Ok(list) = env::var("HTTP_LISTEN") {
+        let addrs: Vec<&str> = list.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()).collect();
+        if addrs.is_empty() {
+            problems.push(String::from("HTTP_LISTEN: no addresses given"));
+        }
+        for addr in addrs {
+            if addr.rsplit_once(':').is_none_or(|(_, port)| port.parse::<u16>().is_err()) {
+                problems.push(format!("HTTP_LISTEN: {addr} is not host:port"));
+            }
+        }
+    }
+    if unix_socket {
+        if cfg!(not(unix)) {
+            problems.push(String::from("LISTEN_UNIX_SOCKET: only supported on unix"));
+        }
+        if ["TLS_CERT_PATH", "TLS_KEY_PATH", "ACME_DOMAINS"].iter().any(|v| env::var(v).is_ok()) {
+            problems.push(String::from("LISTEN_UNIX_SOCKET: can't be combined with TLS or ACME, the proxy terminates TLS"));
+        }
+    }
+
+    match (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+        (Ok(cert), Ok(key)) => {
+            for (name, path) in [("TLS_CERT_PATH", cert), ("TLS_KEY_PATH", key)] {
+                if !Path::new(&path).is_file() {
+                    problems.push(format!("{name}: {path} is not a file"));
+                }
+            }
+        }
+        (Ok(_), Err(_)) => problems.push(String::from("TLS_KEY_PATH: missing, required with TLS_CERT_PATH")),
+        (Err(_), Ok(_)) => problems.push(String::from("TLS_CERT_PATH: missing, required with TLS_KEY_PATH")),
+        (Err(_), Err(_)) => {}
+    }
+
+    if let Ok(domains) = env::var("ACME_DOMAINS") {
+        if domains.split(',').all(|d| d.trim().is_empty()) {
+            problems.push(String::from("ACME_DOMAINS: no domains given"));
+        }
+        if env::var("TLS_CERT_PATH").is_ok() || env::var("TLS_KEY_PATH").is_ok() {
+            problems.push(String::from("ACME_DOMAINS: can't be combined with TLS_CERT_PATH/TLS_KEY_PATH"));
+        }
+    }
+
+    for name in ["MAX_TRANSFERS", "MAX_TRANSFERS_PER_USER"] {
+        if let Ok(v) = env::var(name) {
+            if v.parse::<usize>().is_err() {
+                problems.push(format!("{name}: {v} is not a non-negative integer"));
+            }
+        }
+    }
+    for name in ["GIT_BROWSING", "ARCHIVE_BROWSING", "AUTO_CREATE_DIRECTORIES", "COMPRESSION", "ACME_STAGING", "CORS_ALLOW_CREDENTIALS", "POSIX_PERMISSIONS", "CHECKSUM_SIDECARS", "SECURITY_HEADERS"] {
+        if let Ok(v) = env::var(name) {
+            if v != "true" && v != "false" {
+                problems.push(format!("{name}: expected true or false, got {v}"));
+            }
+        }
+    }
+    if let Ok(v) = env::var("ETAG_STRATEGY") {
+        if !["stat", "hash", "off"].contains(&v.as_str()) {
+            problems.push(format!("ETAG_STRATEGY: expected stat, hash or off, got {v}"));
+        }
+    }
+
+    for name in ["HEADER_READ_TIMEOUT", "IDLE_TIMEOUT", "REQUEST_TIMEOUT", "SHUTDOWN_GRACE_PERIOD"] {
+        if let Ok(v) = env::var(name) {
+            if v.parse::<u64>().is_err() {
+                problems.push(format!("{name}: {v} is not a number of seconds"));
+            }
+        }
+    }
+    if let Ok(v) = env::var("COMPRESSION_MIN_SIZE") {
+        if v.parse::<u64>().is_err() {
+            problems.push(format!("COMPRESSION_MIN_SIZE: {v} is not a non-negative integer"));
+        }
+    }
+    for name in ["DIRECTORY_MODE", "LISTEN_UNIX_SOCKET_MODE"] {
+        if let Ok(v) = env::var(name) {
+            if provision::parse_mode(&v).is_none() {
+                problems.push(format!("{name}: {v} is not an octal permission mode"));
+            }
+        }
+    }
+
+    if let Ok(path) = env::var("USAGE_JSON_PATH") {
+        let parent = Path::new(&path).parent().filter(|p| !p.as_os_str().is_empty());
+        if parent.is_some_and(|p| !p.is_dir()) {
+            problems.push(format!("USAGE_JSON_PATH: parent directory of {path} does not exist"));
+        }
+        problems.extend(usage::check(Path::new(&path)));
+    }
+    if let Ok(url) = env::var("OFFICE_CONVERTER_URL") {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            problems.push(format!("OFFICE_CONVERTER_URL: {url} is not an http(s) URL"));
+        }
+    }
+    if cfg!(not(unix)) && env::var("POSIX_PERMISSIONS").is_ok_and(|v| v == "true") {
+        problems.push(String::from("POSIX_PERMISSIONS: only supported on unix"));
+    }
+    if cfg!(not(unix)) && env::var("LABEL_XATTR").is_ok() {
+        problems.push(String::from("LABEL_XATTR: only supported on unix"));
+    }
+    if let Ok(dir) = env::var("SKELETON_DIR") {
+        if !Path::new(&dir).is_dir() {
+            problems.push(format!("SKELETON_DIR: {dir} is not a directory"));
+        }
+    }
+    if let Ok(dir) = env::var("ERROR_PAGES_DIR") {
+        if !Path::new(&dir).is_dir() {
+            problems.push(format!("ERROR_PAGES_DIR: {dir} is not a directory"));
+        }
+    }
+    if let Ok(dir) = env::var("WELL_KNOWN_DIR") {
+        if !Path::new(&dir).is_dir() {
+            problems.push(format!("WELL_KNOWN_DIR: {dir} is not a directory"));
+        }
+    }
+    if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+        if let Err(e) = cors::layer(
+            &origins,
+            env::var("CORS_ALLOWED_METHODS").ok(),
+            env::var("CORS_ALLOWED_HEADERS").ok(),
+            env::var("CORS_ALLOW_CREDENTIALS").is_ok_and(|v| v == "true"),
+        ) {
+            problems.push(e);
+        }
+    }
+    if let Ok(base_path) = env::var("BASE_PATH") {
+        problems.extend(base_path::check(&base_path));
+    }
+    if let Err(e) = proxy::Proxies::from_env(env::var("TRUSTED_PROXIES").ok(), "http") {
+        problems.push(e);
+    }
+    if let Err(e) = security::SecurityHeaders::from_env(
+        env::var("SECURITY_HEADERS").ok(),
+        env::var("CONTENT_SECURITY_POLICY").ok(),
+        env::var("REFERRER_POLICY").ok(),
+        env::var("FRAME_OPTIONS").ok(),
+    ) {
+        problems.push(e);
+    }
+    if let Ok(path) = env::var("RESPONSE_HEADERS_PATH") {
+        problems.extend(headers::check_rules(&path));
+    }
+    if let Ok(path) = env::var("CACHE_CONTROL_PATH") {
+        problems.extend(cache_control::check_rules(&path));
+    }
+
+    Report {
+        errors: problems,
+        warnings,
+    }
+}
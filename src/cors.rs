@@ -0,0 +1,58 @@
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Methods allowed when CORS_ALLOWED_METHODS is unset; the server is read-only.
+const DEFAULT_METHODS: &str = "GET,HEAD";
+
+/// Request headers allowed when CORS_ALLOWED_HEADERS is unset: credentials and
+/// the conditional/range headers a download manager or player sends.
+const DEFAULT_HEADERS: &str = "authorization,range,if-range,if-none-match,if-modified-since";
+
+/// Response headers scripts on other origins may read, so a SPA can name a
+/// download and show its progress.
+const EXPOSED_HEADERS: [HeaderName; 6] = [
+    header::CONTENT_DISPOSITION,
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+/// CORS for browser apps on other origins. `origins` is `*` or a comma
+/// separated list of origins like `https://app.example.com`. Preflight
+/// requests are answered here, before authentication.
+pub(crate) fn layer(
+    origins: &str,
+    methods: Option<String>,
+    headers: Option<String>,
+    credentials: bool,
+) -> Result<CorsLayer, String> {
+    let allow_origin = if origins.trim() == "*" {
+        if credentials {
+            return Err(String::from("CORS_ALLOW_CREDENTIALS can't be combined with any origin (*)"));
+        }
+        AllowOrigin::any()
+    } else {
+        let origins = list(origins)
+            .map(|o| HeaderValue::from_str(o).map_err(|_| format!("CORS_ALLOWED_ORIGINS: invalid origin {o}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    let methods = list(methods.as_deref().unwrap_or(DEFAULT_METHODS))
+        .map(|m| Method::from_bytes(m.as_bytes()).map_err(|_| format!("CORS_ALLOWED_METHODS: invalid method {m}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    let headers = list(headers.as_deref().unwrap_or(DEFAULT_HEADERS))
+        .map(|h| HeaderName::try_from(h).map_err(|_| format!("CORS_ALLOWED_HEADERS: invalid header {h}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(EXPOSED_HEADERS)
+        .allow_credentials(credentials))
+}
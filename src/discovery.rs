@@ -0,0 +1,91 @@
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderValue, Response},
+    response::IntoResponse,
+    Extension, Json,
+};
+use log::{debug, info};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+use crate::{auth::AuthenticatedUser, is_safe, Context};
+
+/// Features and limits of this server, for clients to discover before they
+/// start probing endpoints.
+fn server_capabilities(ctx: &Context) -> Value {
+    json!({
+        "server": "fileserver",
+        "version": env!("CARGO_PKG_VERSION"),
+        "auth": ["basic"],
+        "read_only": true,
+        "features": {
+            "git_browsing": ctx.git_browsing,
+            "archive_browsing": ctx.archive_browsing,
+            "office_previews": ctx.previews.is_some(),
+            "playlists": true,
+            "feeds": true,
+            "timeline": true,
+            "checksums": ctx.checksum_sidecars,
+        },
+        "limits": {
+            "max_transfers": ctx.transfers.max_total(),
+            "max_transfers_per_user": ctx.transfers.max_per_user(),
+        },
+    })
+}
+
+pub(crate) async fn well_known_fileserver(State(ctx): State<Context>) -> impl IntoResponse {
+    Json(server_capabilities(&ctx))
+}
+
+/// Server capabilities narrowed to what the authenticated user can actually do.
+pub(crate) async fn user_capabilities(
+    State(ctx): State<Context>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> impl IntoResponse {
+    let mut capabilities = server_capabilities(&ctx);
+    capabilities["features"]["admin"] = json!(user.admin);
+    capabilities["limits"]["max_transfers_per_user"] = json!(user.max_transfers.or(ctx.transfers.max_per_user()));
+    capabilities["limits"]["monthly_transfer_limit"] = json!(user.monthly_transfer_limit);
+    capabilities["limits"]["monthly_transfer_used"] = json!(ctx.usage.current(&user.username));
+    capabilities["user"] = json!({
+        "username": user.username,
+        "impersonated_by": user.impersonated_by,
+    });
+    Json(capabilities)
+}
+
+/// Static files from WELL_KNOWN_DIR, served without authentication.
+pub(crate) async fn well_known_file(State(ctx): State<Context>, Path(path): Path<String>) -> Response<Body> {
+    let not_found = || Response::builder().status(404).body("Not Found".into()).unwrap();
+    let Some(dir) = &ctx.well_known_dir else {
+        return not_found();
+    };
+    let file_path = dir.join(&path);
+    if !file_path.is_file() || !is_safe(&file_path, dir.to_str().unwrap()) {
+        info!("404 /.well-known/{path}");
+        return not_found();
+    }
+    match tokio::fs::read(&file_path).await {
+        Ok(data) => {
+            let filetype = mime_guess::from_path(&file_path).first_or_octet_stream();
+            Response::builder()
+                .status(200)
+                .header(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_str(filetype.essence_str()).unwrap(),
+                )
+                .body(Body::from(data))
+                .unwrap()
+        }
+        Err(e) => {
+            debug!("{e}");
+            not_found()
+        }
+    }
+}
+
+pub(crate) fn well_known_dir(value: Option<String>) -> Option<PathBuf> {
+    value.map(|v| std::fs::canonicalize(v).expect("Invalid Env var: WELL_KNOWN_DIR"))
+}
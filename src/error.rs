@@ -0,0 +1,55 @@
+use axum::{
+    body::Body,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+};
+use log::{debug, error, warn};
+use std::{any::Any, io};
+
+/// Why a request couldn't be served, mapped to the status the client sees
+/// instead of unwrapping and dropping the connection.
+pub(crate) enum RequestError {
+    Io(io::Error),
+    Internal(String),
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+impl IntoResponse for RequestError {
+    fn into_response(self) -> Response<Body> {
+        let (status, message) = match self {
+            RequestError::Io(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("404 {e}");
+                (StatusCode::NOT_FOUND, "Not Found")
+            }
+            RequestError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                warn!("403 {e}");
+                (StatusCode::FORBIDDEN, "Forbidden")
+            }
+            RequestError::Io(e) => {
+                error!("500 {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            }
+            RequestError::Internal(message) => {
+                error!("500 {message}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+            }
+        };
+        Response::builder().status(status).body(message.into()).unwrap()
+    }
+}
+
+/// Turns a panic anywhere in the handlers into a logged 500 rather than a
+/// connection dropped without a response.
+pub(crate) fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response<Body> {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    RequestError::Internal(format!("panic: {message}")).into_response()
+}
@@ -0,0 +1,80 @@
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue};
+use axum::{middleware::Next, response::Response};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::feed::xml_escape;
+
+/// HTML templates for error responses, so operators can return branded,
+/// helpful pages instead of the bare "Not Found" strings. A directory holds
+/// `404.html`, `401.html`, `500.html`..., with `error.html` as the fallback for
+/// any status without its own page. `{{status}}`, `{{reason}}` and `{{path}}`
+/// are replaced with the status code, its reason phrase and the request path.
+pub(crate) struct ErrorPages {
+    dir: Option<PathBuf>,
+}
+
+/// A user's own error page directory (`error_pages` in users.json), attached to
+/// their responses so it takes precedence over ERROR_PAGES_DIR.
+#[derive(Clone)]
+pub(crate) struct UserErrorPages(pub(crate) PathBuf);
+
+impl ErrorPages {
+    pub(crate) fn from_env(dir: Option<String>) -> ErrorPages {
+        ErrorPages {
+            dir: dir.map(PathBuf::from),
+        }
+    }
+}
+
+async fn template(dir: &Path, status: u16) -> Option<String> {
+    for name in [format!("{status}.html"), String::from("error.html")] {
+        if let Ok(page) = tokio::fs::read_to_string(dir.join(name)).await {
+            return Some(page);
+        }
+    }
+    None
+}
+
+/// Replaces the plain-text body of 4xx and 5xx responses with the matching
+/// template, keeping the status and headers such as WWW-Authenticate or
+/// Retry-After. Responses that already carry a typed body (JSON, HTML) are left
+/// alone.
+pub(crate) async fn apply(State(pages): State<Arc<ErrorPages>>, req: Request, next: Next) -> Response {
+    let path = String::from(req.uri().path());
+    let mut response = next.run(req).await;
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+    let plain = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .is_none_or(|t| t.starts_with("text/plain"));
+    if !plain {
+        return response;
+    }
+    let user_dir = response.extensions().get::<UserErrorPages>().map(|p| p.0.clone());
+    let mut page = None;
+    for dir in user_dir.iter().chain(pages.dir.iter()) {
+        page = template(dir, status.as_u16()).await;
+        if page.is_some() {
+            break;
+        }
+    }
+    let Some(page) = page else {
+        return response;
+    };
+    let page = page
+        .replace("{{status}}", status.as_str())
+        .replace("{{reason}}", status.canonical_reason().unwrap_or(""))
+        .replace("{{path}}", &xml_escape(&path));
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    headers.remove(header::CONTENT_LENGTH);
+    *response.body_mut() = Body::from(page);
+    response
+}
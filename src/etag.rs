@@ -0,0 +1,270 @@
+use axum::http::HeaderValue;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{File, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::status::CacheStats;
+
+/// How ETags are derived. `Stat` is free but changes whenever a file is touched
+/// and is unreliable on some network filesystems; `Hash` is strong but costs a
+/// full read the first time a given version of a file is served.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum EtagStrategy {
+    Off,
+    Stat,
+    Hash,
+}
+
+impl EtagStrategy {
+    pub(crate) fn from_env(value: Option<String>) -> Self {
+        match value.as_deref() {
+            None | Some("stat") => EtagStrategy::Stat,
+            Some("hash") => EtagStrategy::Hash,
+            Some("off") => EtagStrategy::Off,
+            Some(other) => panic!("Invalid Env var: ETAG_STRATEGY={other} (expected stat, hash or off)"),
+        }
+    }
+}
+
+/// Upper bound on remembered content hashes before the cache is reset.
+const MAX_CACHED_HASHES: usize = 10_000;
+
+struct CachedHash {
+    len: u64,
+    modified: SystemTime,
+    hash: String,
+}
+
+pub(crate) struct Etags {
+    strategy: EtagStrategy,
+    hashes: Mutex<HashMap<PathBuf, CachedHash>>,
+    stats: CacheStats,
+}
+
+impl Etags {
+    pub(crate) fn new(strategy: EtagStrategy) -> Self {
+        Etags {
+            strategy,
+            hashes: Mutex::new(HashMap::new()),
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Hits and misses of the content hash cache; only used by the hash strategy.
+    pub(crate) fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    pub(crate) async fn etag(&self, path: &Path, meta: &Metadata) -> Option<String> {
+        match self.strategy {
+            EtagStrategy::Off => None,
+            EtagStrategy::Stat => Some(stat_etag(meta)),
+            EtagStrategy::Hash => self.hash_etag(path, meta).await,
+        }
+    }
+
+    async fn hash_etag(&self, path: &Path, meta: &Metadata) -> Option<String> {
+        Some(format!("\"{}\"", self.sha256(path, meta).await?))
+    }
+
+    /// Hex SHA-256 of a file, remembered per version like the hash ETags,
+    /// whatever the strategy, so generated checksum files share the cache.
+    pub(crate) async fn sha256(&self, path: &Path, meta: &Metadata) -> Option<String> {
+        let modified = meta.modified().ok()?;
+        if let Some(cached) = self.hashes.lock().unwrap().get(path) {
+            if cached.len == meta.len() && cached.modified == modified {
+                self.stats.hit();
+                return Some(cached.hash.clone());
+            }
+        }
+        self.stats.miss();
+        let owned_path = path.to_path_buf();
+        let hash = match tokio::task::spawn_blocking(move || hash_file(&owned_path)).await {
+            Ok(Ok(hash)) => hash,
+            Ok(Err(e)) => {
+                debug!("{e}");
+                return None;
+            }
+            Err(e) => {
+                debug!("{e}");
+                return None;
+            }
+        };
+        let mut hashes = self.hashes.lock().unwrap();
+        if hashes.len() >= MAX_CACHED_HASHES {
+            hashes.clear();
+        }
+        hashes.insert(
+            path.to_path_buf(),
+            CachedHash {
+                len: meta.len(),
+                modified,
+                hash: hash.clone(),
+            },
+        );
+        Some(hash)
+    }
+}
+
+fn stat_etag(meta: &Metadata) -> String {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        format!("\"{:x}-{:x}-{:x}\"", meta.ino(), meta.len(), mtime)
+    }
+    #[cfg(not(unix))]
+    {
+        format!("\"{:x}-{:x}\"", meta.len(), mtime)
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether an `If-None-Match` header matches `etag`, using the weak comparison
+/// RFC 9110 prescribes for it (a `W/` prefix is ignored on either side).
+pub(crate) fn if_none_match(header: &HeaderValue, etag: &str) -> bool {
+    let Ok(header) = header.to_str() else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    header
+        .split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+/// `Last-Modified` value for a file, at the one-second precision HTTP dates allow.
+pub(crate) fn last_modified(meta: &Metadata) -> Option<String> {
+    meta.modified().ok().map(httpdate::fmt_http_date)
+}
+
+/// Whether the file is unchanged since the date in an `If-Modified-Since`
+/// header. Unparseable dates are ignored, as RFC 9110 requires.
+pub(crate) fn not_modified_since(header: &HeaderValue, meta: &Metadata) -> bool {
+    let Some(since) = header.to_str().ok().and_then(|h| httpdate::parse_http_date(h).ok()) else {
+        return false;
+    };
+    match meta.modified() {
+        Ok(modified) => httpdate::HttpDate::from(modified) <= httpdate::HttpDate::from(since),
+        Err(_) => false,
+    }
+}
+
+/// Whether a range request may be honored under its `If-Range` header: the
+/// cached copy being resumed must be the current one, or the whole file has to
+/// be sent again. Entity tags use strong comparison, so a weak tag never
+/// matches; a date must equal the file's `Last-Modified` exactly.
+pub(crate) fn if_range(header: &HeaderValue, etag: Option<&str>, meta: &Metadata) -> bool {
+    let Ok(header) = header.to_str() else {
+        return false;
+    };
+    let header = header.trim();
+    if header.starts_with('"') || header.starts_with("W/") {
+        return etag.is_some_and(|etag| !etag.starts_with("W/") && header == etag);
+    }
+    match (httpdate::parse_http_date(header), meta.modified()) {
+        (Ok(date), Ok(modified)) => httpdate::HttpDate::from(date) == httpdate::HttpDate::from(modified),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A file last modified at `secs` past the epoch, removed again on drop.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, secs: u64) -> TempFile {
+            let path = std::env::temp_dir().join(format!("fileserver-etag-{}-{name}", std::process::id()));
+            let f = File::create(&path).unwrap();
+            f.set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+            TempFile(path)
+        }
+
+        fn meta(&self) -> Metadata {
+            std::fs::metadata(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn header(value: &str) -> HeaderValue {
+        HeaderValue::from_str(value).unwrap()
+    }
+
+    fn date(secs: u64) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert!(if_none_match(&header("\"a\""), "\"a\""));
+        assert!(if_none_match(&header("W/\"a\""), "\"a\""));
+        assert!(if_none_match(&header("\"a\""), "W/\"a\""));
+        assert!(if_none_match(&header("\"b\", W/\"a\""), "\"a\""));
+        assert!(!if_none_match(&header("\"b\""), "\"a\""));
+        assert!(!if_none_match(&header("\"a"), "\"a\""));
+    }
+
+    #[test]
+    fn if_none_match_star_matches_anything() {
+        assert!(if_none_match(&header("*"), "\"a\""));
+        assert!(if_none_match(&header("\"b\", *"), "W/\"a\""));
+    }
+
+    #[test]
+    fn if_range_compares_tags_strongly() {
+        let file = TempFile::new("if-range-tag", 1_000_000);
+        let meta = file.meta();
+        assert!(if_range(&header("\"a\""), Some("\"a\""), &meta));
+        assert!(!if_range(&header("\"b\""), Some("\"a\""), &meta));
+        assert!(!if_range(&header("W/\"a\""), Some("W/\"a\""), &meta));
+        assert!(!if_range(&header("\"a\""), Some("W/\"a\""), &meta));
+        assert!(!if_range(&header("W/\"a\""), Some("\"a\""), &meta));
+        assert!(!if_range(&header("\"a\""), None, &meta));
+    }
+
+    #[test]
+    fn if_range_dates_must_match_exactly() {
+        let file = TempFile::new("if-range-date", 1_000_000);
+        let meta = file.meta();
+        assert!(if_range(&header(&date(1_000_000)), None, &meta));
+        assert!(!if_range(&header(&date(999_999)), None, &meta));
+        assert!(!if_range(&header(&date(1_000_001)), None, &meta));
+        assert!(!if_range(&header("yesterday"), None, &meta));
+    }
+
+    #[test]
+    fn not_modified_since_allows_later_dates() {
+        let file = TempFile::new("since", 1_000_000);
+        let meta = file.meta();
+        assert!(not_modified_since(&header(&date(1_000_000)), &meta));
+        assert!(not_modified_since(&header(&date(2_000_000)), &meta));
+        assert!(!not_modified_since(&header(&date(999_999)), &meta));
+        assert!(!not_modified_since(&header("not a date"), &meta));
+    }
+}
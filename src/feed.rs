@@ -0,0 +1,83 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::listing::ListingConfig;
+use crate::error::RequestError;
+use crate::paths;
+
+/// How many of the newest files a feed includes.
+const FEED_ITEMS: usize = 50;
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// RSS 2.0 feed of the newest files in a directory, so a release folder can
+/// be subscribed to. Entries hidden by `.listing.toml`, or that aren't
+/// `visible` to the user, stay hidden.
+pub(crate) fn handle_feed(
+    file_path: PathBuf,
+    base_dir: &Path,
+    origin: &str,
+    visible: &dyn Fn(&Path) -> bool,
+) -> Result<Response<Body>, RequestError> {
+    let mut children = vec![];
+    for entry in file_path.read_dir()?.flatten() {
+        let path = entry.path();
+        if visible(&path) {
+            children.push(path);
+        }
+    }
+    let mut files: Vec<(PathBuf, SystemTime, u64)> = ListingConfig::load(&file_path)
+        .arrange(children)
+        .into_iter()
+        .filter_map(|c| {
+            let meta = c.metadata().ok()?;
+            if !meta.is_file() {
+                return None;
+            }
+            Some((c, meta.modified().ok()?, meta.len()))
+        })
+        .collect();
+    files.sort_by_key(|(_, modified, _)| Reverse(*modified));
+    files.truncate(FEED_ITEMS);
+
+    let dir = paths::relativize(&file_path, base_dir);
+    let href = paths::encode(&dir);
+    let dir = dir.to_string_lossy();
+    let mut r = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\">\n<channel>\n");
+    r.push_str(&format!("<title>{}</title>\n", xml_escape(if dir.is_empty() { "/" } else { &dir })));
+    r.push_str(&format!("<link>{}</link>\n", xml_escape(&format!("{origin}{href}/"))));
+    r.push_str(&format!("<description>New files in {}</description>\n", xml_escape(&dir)));
+    for (path, modified, len) in files {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let filetype = mime_guess::from_path(&path).first_or_octet_stream();
+        let url = xml_escape(&format!("{origin}{}", paths::encode(&paths::relativize(&path, base_dir))));
+        r.push_str("<item>\n");
+        r.push_str(&format!("<title>{}</title>\n", xml_escape(&name)));
+        r.push_str(&format!("<link>{url}</link>\n<guid>{url}</guid>\n"));
+        r.push_str(&format!("<pubDate>{}</pubDate>\n", httpdate::fmt_http_date(modified)));
+        r.push_str(&format!(
+            "<enclosure url=\"{url}\" length=\"{len}\" type=\"{}\"/>\n",
+            filetype.essence_str()
+        ));
+        r.push_str("</item>\n");
+    }
+    r.push_str("</channel>\n</rss>\n");
+
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("application/rss+xml; charset=utf-8"))
+        .body(Body::from(r))
+        .unwrap())
+}
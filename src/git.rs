@@ -0,0 +1,184 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use git2::{ObjectType, Oid, Repository};
+use log::{debug, info};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tokio::io::DuplexStream;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+
+use crate::feed::xml_escape;
+use crate::{content_disposition, is_safe, paths};
+
+/// What `?ref=` resolved to.
+pub(crate) enum Lookup {
+    /// A file at the ref.
+    Blob(Blob),
+    /// The entry names of a directory at the ref. `at_root` is set for the
+    /// repository root, whose parent is the working tree again.
+    Tree { names: Vec<PathBuf>, at_root: bool },
+    NotFound,
+}
+
+/// A file in a repository's object database, read when it's streamed rather
+/// than when it's looked up.
+pub(crate) struct Blob {
+    repo: PathBuf,
+    oid: Oid,
+    size: u64,
+}
+
+impl Blob {
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Streams the blob. Reading runs on the blocking pool and is piped into
+    /// the response, like an archive member.
+    pub(crate) fn stream(&self) -> ReaderStream<DuplexStream> {
+        let (reader, writer) = tokio::io::duplex(64 * 1024);
+        let mut writer = SyncIoBridge::new(writer);
+        let repo = self.repo.clone();
+        let oid = self.oid;
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = copy_blob(&repo, oid, &mut writer) {
+                debug!("{e}");
+            }
+        });
+        ReaderStream::new(reader)
+    }
+}
+
+fn copy_blob(repo: &Path, oid: Oid, out: &mut impl Write) -> io::Result<()> {
+    let repo = Repository::open(repo).map_err(io::Error::other)?;
+    let odb = repo.odb().map_err(io::Error::other)?;
+    //loose objects are inflated as they're read; packed ones can only be loaded whole
+    if let Ok((mut reader, _, _)) = odb.reader(oid) {
+        io::copy(&mut reader, out)?;
+        return Ok(());
+    }
+    let blob = repo.find_blob(oid).map_err(io::Error::other)?;
+    out.write_all(blob.content())
+}
+
+/// Resolves `file_path` as it was at `rev` in the nearest git repository at
+/// or above it (but still inside the user's directory), rather than from the
+/// working tree. The path does not need to exist on disk at all. Blocks on
+/// git2, so it's run on the blocking pool.
+pub(crate) fn lookup(file_path: &Path, base_dir: &str, rev: &str) -> Lookup {
+    for candidate in file_path.ancestors() {
+        if !candidate.starts_with(base_dir) {
+            break;
+        }
+        if !candidate.is_dir() || !is_safe(&candidate.to_path_buf(), base_dir) {
+            continue;
+        }
+        if let Ok(repo) = Repository::open(candidate) {
+            let relative = file_path.strip_prefix(candidate).unwrap();
+            return lookup_in_repo(&repo, candidate, relative, rev);
+        }
+    }
+    info!("404 No git repository for {}", file_path.display());
+    Lookup::NotFound
+}
+
+fn lookup_in_repo(repo: &Repository, path: &Path, relative: &Path, rev: &str) -> Lookup {
+    let root = match repo.revparse_single(rev).and_then(|o| o.peel_to_tree()) {
+        Ok(t) => t,
+        Err(e) => {
+            debug!("{e}");
+            info!("404 Unknown ref {rev}");
+            return Lookup::NotFound;
+        }
+    };
+    let at_root = relative.as_os_str().is_empty();
+    //only the entry is looked at here, a blob isn't loaded until it's streamed
+    let (oid, kind) = if at_root {
+        (root.id(), Some(ObjectType::Tree))
+    } else {
+        match root.get_path(relative) {
+            Ok(entry) => (entry.id(), entry.kind()),
+            Err(e) => {
+                debug!("{e}");
+                info!("404 {} not in {rev}", relative.display());
+                return Lookup::NotFound;
+            }
+        }
+    };
+    match kind {
+        Some(ObjectType::Blob) => match repo.odb().and_then(|odb| odb.read_header(oid)) {
+            Ok((size, _)) => Lookup::Blob(Blob {
+                repo: path.to_path_buf(),
+                oid,
+                size: size as u64,
+            }),
+            Err(e) => {
+                debug!("{e}");
+                Lookup::NotFound
+            }
+        },
+        Some(ObjectType::Tree) => {
+            let Ok(tree) = repo.find_tree(oid) else {
+                return Lookup::NotFound;
+            };
+            let mut names: Vec<PathBuf> = tree
+                .iter()
+                .map(|entry| paths::from_bytes(entry.name_bytes().to_vec()))
+                .collect();
+            names.sort();
+            Lookup::Tree { names, at_root }
+        }
+        _ => Lookup::NotFound,
+    }
+}
+
+pub(crate) fn blob_response(body: Body, file_path: &Path, len: u64) -> Response<Body> {
+    let filetype = mime_guess::from_path(file_path).first_or_octet_stream();
+    let filename = file_path.file_name().map_or("file".into(), |n| n.to_string_lossy());
+    Response::builder()
+        .status(200)
+        .header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(filetype.essence_str()).unwrap(),
+        )
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("attachment", &filename),
+        )
+        .header(header::CONTENT_LENGTH, len)
+        .body(body)
+        .unwrap()
+}
+
+/// Lists `names` below `requested`, the URL path of a directory at `rev`.
+/// Links are prefixed with `base_path` and stay at the same ref.
+pub(crate) fn listing(names: &[PathBuf], at_root: bool, requested: &Path, rev: &str, base_path: &str) -> Response<Body> {
+    let rev = paths::encode_query(rev);
+    //hrefs are built from the encoded path so names that aren't UTF-8 survive
+    let href_base = format!("{base_path}{}", paths::encode(requested));
+    let base = requested.to_string_lossy();
+    let mut r = String::new();
+    if !base.is_empty() {
+        let parent = href_base.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        //leaving the repository root goes back to the working tree
+        if at_root {
+            r.push_str(&format!("<a href=\"{parent}/\">..</a><br>\n"));
+        } else {
+            r.push_str(&format!("<a href=\"{parent}/?ref={rev}\">..</a><br>\n"));
+        }
+    }
+    for name in names {
+        let href = format!("{href_base}/{}?ref={rev}", paths::encode(name));
+        let text = format!("{base}/{}", name.to_string_lossy());
+        r.push_str(&format!("<a href=\"{href}\">{}</a><br>\n", xml_escape(&text)));
+    }
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))
+        .body(Body::from(r))
+        .unwrap()
+}
@@ -0,0 +1,81 @@
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue};
+use axum::{middleware::Next, response::Response};
+use glob::Pattern;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct RawRule {
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+/// Extra response headers added to every response whose request path matches `pattern`.
+pub(crate) struct HeaderRule {
+    pattern: Pattern,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+pub(crate) type HeaderRules = Arc<Vec<HeaderRule>>;
+
+pub(crate) fn load_rules(path: &str) -> HeaderRules {
+    let data = fs::read_to_string(path).expect("Failed to read response headers json");
+    let raw: Vec<RawRule> = serde_json::from_str(&data).expect("Invalid response headers JSON format");
+    let rules = raw
+        .into_iter()
+        .map(|r| HeaderRule {
+            pattern: Pattern::new(&r.path).expect("Invalid path glob in response headers"),
+            headers: r
+                .headers
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        HeaderName::try_from(k.as_str()).expect("Invalid header name in response headers"),
+                        HeaderValue::from_str(v).expect("Invalid header value in response headers"),
+                    )
+                })
+                .collect(),
+        })
+        .collect();
+    Arc::new(rules)
+}
+
+pub(crate) fn check_rules(path: &str) -> Vec<String> {
+    let data = match fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => return vec![format!("{path}: {e}")],
+    };
+    let raw: Vec<RawRule> = match serde_json::from_str(&data) {
+        Ok(r) => r,
+        Err(e) => return vec![format!("{path}: invalid JSON: {e}")],
+    };
+    let mut problems = vec![];
+    for (i, rule) in raw.iter().enumerate() {
+        if let Err(e) = Pattern::new(&rule.path) {
+            problems.push(format!("{path}: rule {i}: path: {e}"));
+        }
+        for (name, value) in &rule.headers {
+            if HeaderName::try_from(name.as_str()).is_err() {
+                problems.push(format!("{path}: rule {i}: invalid header name {name}"));
+            }
+            if HeaderValue::from_str(value).is_err() {
+                problems.push(format!("{path}: rule {i}: invalid value for {name}"));
+            }
+        }
+    }
+    problems
+}
+
+pub(crate) async fn apply_rules(State(rules): State<HeaderRules>, req: Request, next: Next) -> Response {
+    let path = String::from(req.uri().path());
+    let mut response = next.run(req).await;
+    for rule in rules.iter().filter(|r| r.pattern.matches(&path)) {
+        for (name, value) in &rule.headers {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
@@ -0,0 +1,62 @@
+use log::warn;
+use std::path::{Path, PathBuf};
+
+/// Sensitivity labels read from an extended attribute such as
+/// `user.classification`. A labeled file or directory, and everything below a
+/// labeled directory, may only be opened by users whose `clearance` lists the
+/// label; unlabeled paths are open to everyone.
+pub(crate) struct Labels {
+    xattr: String,
+}
+
+impl Labels {
+    pub(crate) fn from_env(xattr: Option<String>) -> Option<Labels> {
+        xattr.map(|xattr| Labels { xattr })
+    }
+
+    pub(crate) fn label(&self, path: &Path) -> Option<String> {
+        read(path, &self.xattr)
+    }
+
+    /// The first label from `base` down to `path` that `clearance` doesn't
+    /// cover, if any.
+    pub(crate) fn denied(&self, path: &Path, base: &Path, clearance: &[String]) -> Option<String> {
+        let relative = path.strip_prefix(base).ok()?;
+        let mut current = PathBuf::from(base);
+        let mut paths = vec![current.clone()];
+        for c in relative.components() {
+            current.push(c);
+            paths.push(current.clone());
+        }
+        paths
+            .iter()
+            .filter_map(|p| self.label(p))
+            .find(|label| !clearance.contains(label))
+    }
+}
+
+/// Stands in for a label that couldn't be read.
+#[cfg(unix)]
+const UNREADABLE: &str = "unreadable";
+
+#[cfg(unix)]
+fn read(path: &Path, name: &str) -> Option<String> {
+    match xattr::get(path, name) {
+        Ok(Some(value)) => {
+            let label = String::from_utf8_lossy(&value).trim().to_string();
+            (!label.is_empty()).then_some(label)
+        }
+        Ok(None) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => None,
+        //fail closed: a label that can't be read might be one the user isn't cleared for
+        Err(e) => {
+            warn!("Failed to read {name} of {}: {e}", path.display());
+            Some(String::from(UNREADABLE))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn read(_path: &Path, _name: &str) -> Option<String> {
+    None
+}
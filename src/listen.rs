@@ -0,0 +1,125 @@
+use axum::{extract::ConnectInfo, Extension, Router};
+use axum_server::Handle;
+use log::{info, warn};
+use std::{env, net::SocketAddr, net::TcpListener};
+
+use crate::timeouts::Timeouts;
+
+#[cfg(unix)]
+pub(crate) type UnixAddr = std::os::unix::net::SocketAddr;
+#[cfg(unix)]
+pub(crate) type UnixListener = std::os::unix::net::UnixListener;
+#[cfg(not(unix))]
+pub(crate) type UnixAddr = SocketAddr;
+#[cfg(not(unix))]
+pub(crate) type UnixListener = std::convert::Infallible;
+
+/// A socket passed in by systemd socket activation.
+pub(crate) enum Inherited {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// The listening socket systemd passed via LISTEN_FDS, if any, so systemd can
+/// own the socket for on-demand start and restarts that drop no connections.
+/// Only the first socket is used.
+#[cfg(unix)]
+pub(crate) fn systemd() -> Option<Inherited> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+    //SD_LISTEN_FDS_START
+    const FIRST_FD: i32 = 3;
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+    let fds: i32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if fds < 1 {
+        return None;
+    }
+    if fds > 1 {
+        warn!("systemd passed {fds} sockets, only the first is used");
+    }
+    //safety: per sd_listen_fds(3) the fd is open and handed to this process
+    let tcp = unsafe { TcpListener::from_raw_fd(FIRST_FD) };
+    tcp.set_nonblocking(true).unwrap();
+    //getsockname only yields an address for inet sockets
+    if tcp.local_addr().is_ok() {
+        return Some(Inherited::Tcp(tcp));
+    }
+    Some(Inherited::Unix(unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) }))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn systemd() -> Option<Inherited> {
+    None
+}
+
+/// The inherited listener, or one bound to each address in HTTP_LISTEN
+/// (comma separated `host:port`, e.g. a LAN IPv4 and a public IPv6 address),
+/// or else to HTTP_HOST:HTTP_PORT.
+pub(crate) fn tcp(inherited: Option<TcpListener>) -> Vec<TcpListener> {
+    if let Some(listener) = inherited {
+        return vec![listener];
+    }
+    let addrs: Vec<String> = match env::var("HTTP_LISTEN") {
+        Ok(list) => list.split(',').map(|a| String::from(a.trim())).filter(|a| !a.is_empty()).collect(),
+        Err(_) => {
+            let host = env::var("HTTP_HOST").expect("Missing Env var: HTTP_HOST");
+            let port = env::var("HTTP_PORT").expect("Missing Env var: HTTP_PORT");
+            vec![format!("{host}:{port}")]
+        }
+    };
+    addrs
+        .iter()
+        .map(|addr| {
+            let listener = TcpListener::bind(addr).unwrap_or_else(|e| panic!("Failed to bind {addr}: {e}"));
+            listener.set_nonblocking(true).unwrap();
+            listener
+        })
+        .collect()
+}
+
+/// Binds a unix domain socket, for sitting behind a local reverse proxy
+/// without opening a port. `mode` is an octal permission mode for the socket
+/// file, so the proxy's user can be let in.
+#[cfg(unix)]
+pub(crate) fn bind_unix(path: &str, mode: Option<String>) -> UnixListener {
+    use std::{fs, os::unix::fs::FileTypeExt, os::unix::fs::PermissionsExt};
+    //a socket left behind by an earlier run would make bind fail
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        fs::remove_file(path).expect("Failed to remove stale LISTEN_UNIX_SOCKET");
+    }
+    let listener = UnixListener::bind(path).expect("Failed to bind LISTEN_UNIX_SOCKET");
+    if let Some(mode) = mode {
+        let mode = crate::provision::parse_mode(&mode).expect("Invalid Env var: LISTEN_UNIX_SOCKET_MODE");
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).expect("Failed to set LISTEN_UNIX_SOCKET_MODE");
+    }
+    listener
+}
+
+#[cfg(not(unix))]
+pub(crate) fn bind_unix(_path: &str, _mode: Option<String>) -> UnixListener {
+    panic!("LISTEN_UNIX_SOCKET is only supported on unix");
+}
+
+#[cfg(unix)]
+pub(crate) async fn serve_unix(app: Router, listener: UnixListener, timeouts: &Timeouts, handle: Handle<UnixAddr>) {
+    listener.set_nonblocking(true).unwrap();
+    match listener.local_addr().ok().and_then(|a| a.as_pathname().map(|p| p.display().to_string())) {
+        Some(path) => info!("Starting webserver on unix:{path}"),
+        None => info!("Starting webserver on a unix socket"),
+    }
+    //peers are the local proxy, so clients are reported as loopback
+    let app = app.layer(Extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0)))));
+    timeouts
+        .apply(axum_server::from_unix(listener).unwrap())
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await
+        .unwrap();
+}
+
+#[cfg(not(unix))]
+pub(crate) async fn serve_unix(_app: Router, listener: UnixListener, _timeouts: &Timeouts, _handle: Handle<UnixAddr>) {
+    match listener {}
+}
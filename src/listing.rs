@@ -0,0 +1,187 @@
+use glob::Pattern;
+use log::warn;
+use serde::Deserialize;
+use std::{
+    cmp::Reverse,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Per-directory listing tweaks, read from `.listing.toml` in the directory:
+///
+/// ```toml
+/// sort = "modified"        # name (default), modified, size or version
+/// reverse = true
+/// pinned = ["setup.exe"]   # shown first, in this order
+/// hidden = ["*.tmp"]       # globs matched against entry names
+/// releases = true          # see below
+/// ```
+///
+/// A releases directory holds build artifacts: entries are sorted by version,
+/// newest first (overriding `sort` and `reverse`), `dir/latest` redirects to
+/// the newest one and its CHANGELOG.md is shown above the listing.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ListingConfig {
+    sort: SortKey,
+    reverse: bool,
+    pinned: Vec<String>,
+    hidden: Vec<String>,
+    releases: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    #[default]
+    Name,
+    Modified,
+    Size,
+    Version,
+}
+
+/// Version embedded in a name such as `app-1.10.2-rc1.tar.gz`. Pre-releases
+/// order before the release they lead up to.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Version {
+    numbers: Vec<u64>,
+    release: bool,
+    pre: String,
+}
+
+const PRE_RELEASE_TAGS: [&str; 6] = ["alpha", "beta", "rc", "pre", "dev", "snapshot"];
+
+/// A dotted run of digits is preferred, so `x86_64-2.1` is version 2.1; a run
+/// glued to a word (`app2`) isn't a version.
+fn version(name: &str) -> Option<Version> {
+    let bytes = name.as_bytes();
+    let mut candidates = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || (bytes[i] == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))) {
+            i += 1;
+        }
+        let glued = start > 0 && bytes[start - 1].is_ascii_alphabetic() && !matches!(bytes[start - 1], b'v' | b'V');
+        if !glued {
+            candidates.push((start, i));
+        }
+    }
+    let (start, end) = candidates
+        .iter()
+        .find(|(s, e)| name[*s..*e].contains('.'))
+        .or(candidates.first())
+        .copied()?;
+    let numbers = name[start..end].split('.').map(|n| n.parse().unwrap_or(u64::MAX)).collect();
+    //`-rc1` marks a pre-release, while `-linux-x64` is a platform, not a pre-release
+    let pre = name[end..]
+        .strip_prefix(['-', '~', '_'])
+        .and_then(|rest| rest.split(['.', '-', '_']).next())
+        .filter(|tag| PRE_RELEASE_TAGS.iter().any(|t| tag.to_ascii_lowercase().starts_with(t)))
+        .unwrap_or("");
+    Some(Version {
+        numbers,
+        release: pre.is_empty(),
+        pre: pre.to_ascii_lowercase(),
+    })
+}
+
+/// Changelog shown above a releases listing, first one found.
+pub(crate) const CHANGELOGS: [&str; 2] = ["CHANGELOG.md", "CHANGES.md"];
+
+pub(crate) const LISTING_CONFIG: &str = ".listing.toml";
+
+impl ListingConfig {
+    pub(crate) fn load(dir: &Path) -> Self {
+        let path = dir.join(LISTING_CONFIG);
+        match fs::read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).unwrap_or_else(|e| {
+                warn!("Ignoring invalid {}: {e}", path.display());
+                ListingConfig::default()
+            }),
+            Err(_) => ListingConfig::default(),
+        }
+    }
+
+    pub(crate) fn releases(&self) -> bool {
+        self.releases
+    }
+
+    /// Drops hidden entries and orders the rest: pinned entries first, then by
+    /// the configured sort key.
+    pub(crate) fn arrange(&self, children: Vec<PathBuf>) -> Vec<PathBuf> {
+        let hidden: Vec<Pattern> = self.hidden.iter().filter_map(|h| Pattern::new(h).ok()).collect();
+        let mut children: Vec<PathBuf> = children
+            .into_iter()
+            .filter(|c| {
+                let n = name(c);
+                n != LISTING_CONFIG && !hidden.iter().any(|h| h.matches(&n))
+            })
+            .collect();
+
+        children.sort();
+        let (sort, reverse) = if self.releases {
+            (&SortKey::Version, true)
+        } else {
+            (&self.sort, self.reverse)
+        };
+        match sort {
+            SortKey::Name => {}
+            SortKey::Modified => children.sort_by_key(|c| fs::metadata(c).and_then(|m| m.modified()).ok()),
+            SortKey::Size => children.sort_by_key(|c| fs::metadata(c).map(|m| m.len()).unwrap_or(0)),
+            SortKey::Version => children.sort_by_key(|c| version(&name(c))),
+        }
+        if reverse {
+            children.reverse();
+        }
+        //pinned entries move to the front in the order given; the stable sort keeps the rest as they were
+        children.sort_by_key(|c| {
+            let n = name(c);
+            Reverse(self.pinned.iter().rev().position(|p| *p == n))
+        });
+        children
+    }
+
+    /// The visible entry with the highest version, which `dir/latest` stands
+    /// for. Pre-releases only count when there is no release yet.
+    pub(crate) fn newest(&self, children: Vec<PathBuf>) -> Option<PathBuf> {
+        self.arrange(children)
+            .into_iter()
+            .filter_map(|c| Some((version(&name(&c))?, c)))
+            .max_by(|a, b| (a.0.release, &a.0).cmp(&(b.0.release, &b.0)))
+            .map(|(_, c)| c)
+    }
+}
+
+/// For a path through `latest` in a releases directory, the same path through
+/// its newest entry, e.g. `app/latest/app.tar.gz` to `app/2.1.0/app.tar.gz`.
+/// Only directories below `base` are considered, and only entries that are
+/// `visible` to the user can be picked as the newest.
+pub(crate) fn latest(file_path: &Path, base: &Path, visible: &dyn Fn(&Path) -> bool) -> Option<PathBuf> {
+    for candidate in file_path.ancestors() {
+        if candidate == base || !candidate.starts_with(base) {
+            break;
+        }
+        if candidate.file_name().is_none_or(|n| n != "latest") {
+            continue;
+        }
+        let dir = candidate.parent()?;
+        let config = ListingConfig::load(dir);
+        if !config.releases {
+            continue;
+        }
+        let children = dir.read_dir().ok()?.flatten().map(|e| e.path()).filter(|c| visible(c)).collect();
+        let newest = config.newest(children)?;
+        let rest = file_path.strip_prefix(candidate).ok()?;
+        return Some(newest.join(rest));
+    }
+    None
+}
+
+fn name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
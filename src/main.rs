@@ -1,27 +1,101 @@
+mod acme;
+mod admin;
+mod archive;
 mod auth;
+mod base_path;
+mod cache_control;
+mod checksums;
+mod compression;
+mod config;
+mod cors;
+mod discovery;
+mod error;
+mod error_pages;
+mod etag;
+mod feed;
+mod git;
+mod headers;
+mod labels;
+mod listen;
+mod listing;
+mod methods;
+mod playlist;
+mod posix;
+mod paths;
+mod preview;
+mod provision;
+mod proxy;
+mod range;
+mod schedule;
+mod security;
+mod shutdown;
+mod status;
+mod subtitles;
+mod timeline;
+mod timeouts;
+mod transfers;
+mod usage;
 
+use axum_server::tls_rustls::RustlsConfig;
 use dotenvy::dotenv;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use std::{
     collections::HashMap,
     env,
-    fs::{canonicalize, exists},
+    fs::{canonicalize, exists, Metadata},
+    io::SeekFrom,
+    net::{IpAddr, SocketAddr},
     path::{Component, Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use axum::{
-    body::Body, http::{header, HeaderValue, Response}, response::IntoResponse, routing::get, Extension, Router
+    body::Body, extract::{Query, State}, http::{header, HeaderMap, HeaderValue, Method, Response, StatusCode, Uri}, response::IntoResponse, routing::{delete, get, options}, Extension, Router
 };
-use mime_guess;
 use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
+use tower_http::catch_panic::CatchPanicLayer;
 
+use crate::archive::{ArchivePath, Entry};
+use crate::cache_control::CacheRules;
+use crate::checksums::Sidecar;
 use crate::auth::AuthenticatedUser;
+use crate::error::RequestError;
+use crate::error_pages::ErrorPages;
+use crate::etag::{EtagStrategy, Etags};
+use crate::git::Lookup;
+use crate::labels::Labels;
+use crate::listen::Inherited;
+use crate::listing::ListingConfig;
+use crate::preview::Previews;
+use crate::provision::Provisioning;
+use crate::proxy::{Client, Proxies};
+use crate::range::RangeRequest;
+use crate::security::SecurityHeaders;
+use crate::status::Status;
+use crate::timeouts::Timeouts;
+use crate::transfers::{LimitReached, TransferGuard, Transfers};
+use crate::usage::Usage;
 
 #[derive(Clone)]
 struct Context {
     users: Arc<HashMap<String, auth::UserData>>,
+    transfers: Arc<Transfers>,
+    git_browsing: bool,
+    archive_browsing: bool,
+    etags: Arc<Etags>,
+    usage: Arc<Usage>,
+    previews: Option<Arc<Previews>>,
+    well_known_dir: Option<PathBuf>,
+    provisioning: Option<Arc<Provisioning>>,
+    cache_rules: CacheRules,
+    status: Arc<Status>,
+    posix_permissions: bool,
+    labels: Option<Arc<Labels>>,
+    base_path: String,
+    checksum_sidecars: bool,
 }
 
 #[tokio::main]
@@ -31,30 +105,254 @@ async fn main() {
     // initialize tracing
     tracing_subscriber::fmt::init();
 
+    //--check validates the configuration and exits, for CI of deployments
+    let check_only = env::args().any(|a| a == "--check");
+    let report = config::check();
+    for p in &report.errors {
+        error!("{p}");
+    }
+    for w in &report.warnings {
+        if check_only {
+            error!("{w}");
+        } else {
+            warn!("{w}");
+        }
+    }
+    let problems = report.errors.len() + if check_only { report.warnings.len() } else { 0 };
+    if problems > 0 {
+        error!("Configuration has {problems} problem(s)");
+        std::process::exit(1);
+    }
+    if check_only {
+        info!("Configuration OK");
+        return;
+    }
+
+    let usage = Arc::new(Usage::load(env::var("USAGE_JSON_PATH").ok().map(PathBuf::from)));
+    {
+        let usage = usage.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(USAGE_SAVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                usage.save();
+            }
+        });
+    }
+
     let ctx = Context {
         users: auth::load_users(
             &env::var("USERS_JSON_PATH").expect("Missing Env var: USERS_JSON_PATH"),
         ),
+        transfers: Arc::new(Transfers::new(
+            env::var("MAX_TRANSFERS")
+                .ok()
+                .map(|v| v.parse().expect("Invalid Env var: MAX_TRANSFERS")),
+            env::var("MAX_TRANSFERS_PER_USER")
+                .ok()
+                .map(|v| v.parse().expect("Invalid Env var: MAX_TRANSFERS_PER_USER")),
+            usage.clone(),
+        )),
+        git_browsing: env::var("GIT_BROWSING").is_ok_and(|v| v == "true"),
+        archive_browsing: env::var("ARCHIVE_BROWSING").is_ok_and(|v| v == "true"),
+        etags: Arc::new(Etags::new(EtagStrategy::from_env(env::var("ETAG_STRATEGY").ok()))),
+        usage,
+        previews: env::var("OFFICE_CONVERTER_URL").ok().map(|url| {
+            let cache_dir = env::var("PREVIEW_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("fileserver-previews"));
+            Arc::new(Previews::new(url, cache_dir))
+        }),
+        well_known_dir: discovery::well_known_dir(env::var("WELL_KNOWN_DIR").ok()),
+        provisioning: Provisioning::from_env(
+            env::var("AUTO_CREATE_DIRECTORIES").ok(),
+            env::var("DIRECTORY_MODE").ok(),
+            env::var("SKELETON_DIR").ok(),
+        )
+        .map(Arc::new),
+        cache_rules: match env::var("CACHE_CONTROL_PATH") {
+            Ok(path) => cache_control::load_rules(&path),
+            Err(_) => Arc::new(vec![]),
+        },
+        status: Arc::new(Status::new()),
+        posix_permissions: env::var("POSIX_PERMISSIONS").is_ok_and(|v| v == "true"),
+        labels: Labels::from_env(env::var("LABEL_XATTR").ok()).map(Arc::new),
+        base_path: base_path::from_env(env::var("BASE_PATH").ok()),
+        checksum_sidecars: env::var("CHECKSUM_SIDECARS").is_ok_and(|v| v == "true"),
     };
+    let status = ctx.status.clone();
+    let connections = ctx.status.clone();
+    let base_path: Arc<str> = Arc::from(ctx.base_path.as_str());
+    let transfers = ctx.transfers.clone();
+    let usage = ctx.usage.clone();
 
-    let app = Router::new()
+    //OPTIONS is answered before basic_auth, so probes and preflights don't need credentials
+    let public = Router::new()
+        .route("/.well-known/fileserver", get(discovery::well_known_fileserver).options(|| methods::options(methods::GET)))
+        .route("/.well-known/{*path}", get(discovery::well_known_file).options(|| methods::options(methods::GET)))
+        .route("/admin/transfers", options(|| methods::options(methods::GET)))
+        .route("/admin/transfers/{id}", options(|| methods::options(methods::DELETE)))
+        .route("/admin/usage", options(|| methods::options(methods::GET)))
+        .route("/admin/status", options(|| methods::options(methods::GET)))
+        .route("/api/capabilities", options(|| methods::options(methods::GET)))
+        .route("/", options(|| methods::options(methods::GET)))
+        .route("/{*wildcard}", options(|| methods::options(methods::GET)));
+
+    let mut app = Router::new()
+        .route("/admin/transfers", get(admin::list_transfers))
+        .route("/admin/transfers/{id}", delete(admin::cancel_transfer))
+        .route("/admin/usage", get(admin::usage))
+        .route("/admin/status", get(admin::status))
+        .route("/api/capabilities", get(discovery::user_capabilities))
         .route("/", get(request_handler))
         .route("/{*wildcard}", get(request_handler))
         .layer(axum::middleware::from_fn_with_state(
             ctx.users.clone(),
             auth::basic_auth,
         ))
+        .merge(public)
         .with_state(ctx);
 
-    let host = env::var("HTTP_HOST").expect("Missing Env var: HTTP_HOST");
-    let port = env::var("HTTP_PORT").expect("Missing Env var: HTTP_PORT");
-    info!("Starting webserver on {host}:{port}");
-    let listener = tokio::net::TcpListener::bind(format!("{host}:{port}"))
-        .await
-        .unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let timeouts = Timeouts::from_env(
+        env::var("HEADER_READ_TIMEOUT").ok(),
+        env::var("IDLE_TIMEOUT").ok(),
+        env::var("REQUEST_TIMEOUT").ok(),
+    );
+    if let Some(layer) = timeouts.layer() {
+        app = app.layer(layer);
+    }
+
+    //error pages replace bodies here, inside the header layers, so they get the same
+    //security and configured headers as any other page; panics become 500s first
+    app = app.layer(CatchPanicLayer::custom(error::panic_response));
+    let error_pages = ErrorPages::from_env(env::var("ERROR_PAGES_DIR").ok());
+    app = app.layer(axum::middleware::from_fn_with_state(Arc::new(error_pages), error_pages::apply));
+
+    let security = SecurityHeaders::from_env(
+        env::var("SECURITY_HEADERS").ok(),
+        env::var("CONTENT_SECURITY_POLICY").ok(),
+        env::var("REFERRER_POLICY").ok(),
+        env::var("FRAME_OPTIONS").ok(),
+    )
+    .unwrap_or_else(|e| panic!("Invalid Env var: {e}"));
+    if let Some(security) = security {
+        app = app.layer(axum::middleware::from_fn_with_state(Arc::new(security), security::apply));
+    }
+
+    if let Ok(path) = env::var("RESPONSE_HEADERS_PATH") {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            headers::load_rules(&path),
+            headers::apply_rules,
+        ));
+    }
+
+    if env::var("COMPRESSION").is_ok_and(|v| v == "true") {
+        app = app.layer(compression::layer(
+            env::var("COMPRESSION_TYPES").ok(),
+            env::var("COMPRESSION_MIN_SIZE").ok(),
+        ));
+    }
+
+    app = app.layer(axum::middleware::from_fn_with_state(status, status::record_errors));
+
+    if let Ok(origins) = env::var("CORS_ALLOWED_ORIGINS") {
+        app = app.layer(
+            cors::layer(
+                &origins,
+                env::var("CORS_ALLOWED_METHODS").ok(),
+                env::var("CORS_ALLOWED_HEADERS").ok(),
+                env::var("CORS_ALLOW_CREDENTIALS").is_ok_and(|v| v == "true"),
+            )
+            .expect("Invalid CORS configuration"),
+        );
+    }
+
+    if !base_path.is_empty() {
+        app = Router::new()
+            .fallback_service(app)
+            .layer(axum::middleware::from_fn_with_state(base_path, base_path::strip));
+    }
+
+    let tls = env::var("TLS_CERT_PATH").is_ok() || env::var("ACME_DOMAINS").is_ok();
+    let proxies = Proxies::from_env(env::var("TRUSTED_PROXIES").ok(), if tls { "https" } else { "http" })
+        .unwrap_or_else(|e| panic!("Invalid Env var: {e}"));
+    app = app.layer(axum::middleware::from_fn_with_state(Arc::new(proxies), proxy::forwarded));
+
+    let grace = shutdown::grace_period(env::var("SHUTDOWN_GRACE_PERIOD").ok());
+    let (inherited, unix) = match listen::systemd() {
+        Some(Inherited::Tcp(listener)) => (Some(listener), None),
+        Some(Inherited::Unix(listener)) => (None, Some(listener)),
+        None => (
+            None,
+            env::var("LISTEN_UNIX_SOCKET")
+                .ok()
+                .map(|path| listen::bind_unix(&path, env::var("LISTEN_UNIX_SOCKET_MODE").ok())),
+        ),
+    };
+    if let Some(listener) = unix {
+        let handle = shutdown::handle(grace, transfers);
+        let counted = handle.clone();
+        connections.count_connections(move || counted.connection_count());
+        listen::serve_unix(app, listener, &timeouts, handle).await;
+        usage.save();
+        info!("Stopped");
+        return;
+    }
+    let listeners = listen::tcp(inherited);
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let handle = shutdown::handle(grace, transfers);
+    let counted = handle.clone();
+    connections.count_connections(move || counted.connection_count());
+    //one server per listener, all sharing the router and the shutdown handle
+    let servers: Vec<_> = if let Ok(domains) = env::var("ACME_DOMAINS") {
+        let acceptor = acme::acceptor(
+            &domains,
+            env::var("ACME_EMAIL").ok(),
+            env::var("ACME_CACHE_DIR").ok(),
+            env::var("ACME_STAGING").is_ok_and(|v| v == "true"),
+        );
+        listeners
+            .into_iter()
+            .map(|listener| {
+                info!("Starting webserver on https://{} with ACME certificates for {domains}", listener.local_addr().unwrap());
+                let server = axum_server::from_tcp(listener).unwrap().acceptor(acceptor.clone());
+                tokio::spawn(timeouts.apply(server).handle(handle.clone()).serve(service.clone()))
+            })
+            .collect()
+    } else if let (Ok(cert), Ok(key)) = (env::var("TLS_CERT_PATH"), env::var("TLS_KEY_PATH")) {
+        //offers h2 and http/1.1 via ALPN
+        let tls = RustlsConfig::from_pem_file(cert, key)
+            .await
+            .expect("Failed to load TLS certificate or key");
+        listeners
+            .into_iter()
+            .map(|listener| {
+                info!("Starting webserver on https://{}", listener.local_addr().unwrap());
+                let server = axum_server::from_tcp_rustls(listener, tls.clone()).unwrap();
+                tokio::spawn(timeouts.apply(server).handle(handle.clone()).serve(service.clone()))
+            })
+            .collect()
+    } else {
+        listeners
+            .into_iter()
+            .map(|listener| {
+                info!("Starting webserver on {}", listener.local_addr().unwrap());
+                let server = axum_server::from_tcp(listener).unwrap();
+                tokio::spawn(timeouts.apply(server).handle(handle.clone()).serve(service.clone()))
+            })
+            .collect()
+    };
+    for server in servers {
+        server.await.unwrap().unwrap();
+    }
+    //transfers cut at the end of the grace period have been counted by now
+    usage.save();
+    info!("Stopped");
 }
 
+/// How often monthly usage counters are written to USAGE_JSON_PATH.
+const USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
 macro_rules! not_found {
     () => {
         Response::builder()
@@ -65,26 +363,81 @@ macro_rules! not_found {
 }
 
 async fn request_handler(
+    State(ctx): State<Context>,
+    Extension(client): Extension<Client>,
     Extension(user): Extension<AuthenticatedUser>,
-    path: Option<axum::extract::Path<String>>,
+    Query(params): Query<HashMap<String, String>>,
+    method: Method,
+    headers: HeaderMap,
+    uri: Uri,
 ) -> impl IntoResponse {
-    let dir= user.directory;
-    let requested_path = match &path {
-        Some(p) => format!("{}{}", "/", p.0.clone()),
-        None => String::from("/"),
+    if let Some(provisioning) = &ctx.provisioning {
+        provisioning.ensure(&user);
+    }
+    //one canonical URL per file, so relative links in listings and READMEs resolve the same way
+    if let Some(collapsed) = paths::collapse_slashes(uri.path()) {
+        return redirect(StatusCode::PERMANENT_REDIRECT, &format!("{}{collapsed}", ctx.base_path), uri.query());
+    }
+    let dir = user.directory.clone();
+    //decoded from the raw URI rather than a String extractor so non-UTF-8 names work
+    let path = paths::decode(uri.path().strip_prefix('/').unwrap_or(uri.path()));
+    let requested_path = format!("/{}", path.to_string_lossy());
+    let username = match &user.impersonated_by {
+        Some(admin) => format!("{admin} as {}", user.username),
+        None => user.username.clone(),
     };
-    let absolute_file_path = match path {
-        Some(p) => Path::new(&dir).join(p.0),
-        None => PathBuf::from(&dir),
+    let Some(absolute_file_path) = paths::join_checked(Path::new(&dir), &path) else {
+        warn!("{method} {username}: 404 Ignored due to malicious request: {requested_path}");
+        return not_found!();
     };
-    info!("GET {}: {} => {}", user.username, requested_path, &absolute_file_path.to_str().unwrap());
+    info!("{method} {} from {}: {} => {}", username, client.ip, requested_path, absolute_file_path.display());
+    if ctx.git_browsing {
+        if let Some(rev) = params.get("ref") {
+            return handle_git(&ctx, &user, client.ip, &absolute_file_path, rev).await;
+        }
+    }
     if exists(&absolute_file_path).unwrap_or(false) {
         if is_safe(&absolute_file_path, &dir) {
+            if let Some(reason) = denied(&ctx, &user, &absolute_file_path) {
+                return forbidden(&absolute_file_path, &reason);
+            }
             if absolute_file_path.is_file() {
+                let cache_control = cache_control::lookup(&ctx.cache_rules, &requested_path, &absolute_file_path);
+                if let Some(response) = not_modified(&ctx, &absolute_file_path, &headers, cache_control.clone()).await {
+                    return response;
+                }
+                if method == Method::HEAD && !params.contains_key("preview") {
+                    return handle_head(&ctx, &absolute_file_path, &headers, cache_control).await;
+                }
+                let transfer = match ctx.transfers.start(&user, &requested_path, client.ip) {
+                    Ok(t) => t,
+                    Err(limit) => return too_many_transfers(limit),
+                };
+                if params.contains_key("preview") && preview::is_office(&absolute_file_path) {
+                    if let Some(previews) = &ctx.previews {
+                        return handle_preview(previews, &absolute_file_path, transfer).await;
+                    }
+                }
                 match File::open(&absolute_file_path).await {
                     Ok(f) => {
-                        info!("200 Success");
-                        handle_file(f, absolute_file_path)
+                        let meta = match f.metadata().await {
+                            Ok(meta) => meta,
+                            Err(e) => {
+                                debug!("{e}");
+                                return not_found!();
+                            }
+                        };
+                        let etag = ctx.etags.etag(&absolute_file_path, &meta).await;
+                        let range = range::requested(&headers, etag.as_deref(), &meta);
+                        match range {
+                            RangeRequest::Unsatisfiable => {
+                                info!("416 Range not satisfiable");
+                                return range::not_satisfiable(meta.len());
+                            }
+                            RangeRequest::Partial { start, end } => info!("206 Partial content {start}-{end}"),
+                            RangeRequest::Full => info!("200 Success"),
+                        }
+                        handle_file(f, absolute_file_path, transfer, etag, cache_control, meta, range).await
                     }
                     Err(e) => {
                         debug!("{e}");
@@ -93,27 +446,278 @@ async fn request_handler(
                 }
             } else {
                 if absolute_file_path.is_dir() {
+                    if !uri.path().ends_with('/') {
+                        info!("301 Redirect to trailing slash");
+                        let location = format!("{}{}/", ctx.base_path, uri.path());
+                        return redirect(StatusCode::MOVED_PERMANENTLY, &location, uri.query());
+                    }
                     info!("200 Success");
-                    handle_dir(absolute_file_path, &PathBuf::from(dir))
+                    let playlist = params.get("playlist").map(|p| p.as_str());
+                    let origin = format!("{}{}", client.origin, ctx.base_path);
+                    //listings of every kind leave out what the user couldn't open
+                    let visible = |p: &Path| denied(&ctx, &user, p).is_none();
+                    if playlist == Some("m3u8") || playlist == Some("m3u") {
+                        return playlist::handle_playlist(absolute_file_path, &PathBuf::from(dir), &origin, &visible)
+                            .into_response();
+                    }
+                    if params.get("format").is_some_and(|f| f == "rss") {
+                        return feed::handle_feed(absolute_file_path, &PathBuf::from(dir), &origin, &visible).into_response();
+                    }
+                    if params.get("view").is_some_and(|v| v == "timeline") {
+                        let base_dir = PathBuf::from(dir);
+                        let (ctx, user) = (ctx.clone(), user.clone());
+                        return tokio::task::spawn_blocking(move || {
+                            let visible = |p: &Path| denied(&ctx, &user, p).is_none();
+                            timeline::handle_timeline(absolute_file_path, &base_dir, &ctx.base_path, &visible)
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(RequestError::Internal(e.to_string())))
+                        .into_response();
+                    }
+                    //browsers ask for html; curl and scripts get one path per line
+                    let labels = ctx.labels.as_deref();
+                    let html = wants_html(&headers);
+                    handle_dir(absolute_file_path, &PathBuf::from(dir), &ctx.base_path, html, &visible, labels).into_response()
                 } else {
-                    warn!("500 unexpected code path: Not file or directory?");
-                    Response::builder()
-                        .status(500)
-                        .body("Internal server error".into())
-                        .unwrap()
+                    RequestError::Internal(String::from("unexpected code path: Not file or directory?")).into_response()
                 }
             }
         } else {
             warn!(
                 "404 Ignored due to malicious request: {}",
-                absolute_file_path.to_str().unwrap()
+                absolute_file_path.display()
             );
-            return not_found!();
+            not_found!()
         }
     } else {
+        if let Some(srt) = subtitles::srt_sidecar(&absolute_file_path) {
+            if is_safe(&srt, &dir) && denied(&ctx, &user, &srt).is_none() {
+                match tokio::fs::read(&srt).await {
+                    Ok(data) => {
+                        info!("200 Success (converted from {})", srt.display());
+                        return subtitles::vtt_response(subtitles::srt_to_vtt(&String::from_utf8_lossy(&data)));
+                    }
+                    Err(e) => debug!("{e}"),
+                }
+            }
+        }
+        let visible = |p: &Path| denied(&ctx, &user, p).is_none();
+        if let Some(newest) = listing::latest(&absolute_file_path, Path::new(&dir), &visible) {
+            let location = format!("{}{}", ctx.base_path, paths::encode(&paths::relativize(&newest, Path::new(&dir))));
+            info!("302 Latest release: {}", newest.display());
+            return redirect(StatusCode::FOUND, &location, uri.query());
+        }
+        if ctx.checksum_sidecars {
+            if let Some(sidecar) = checksums::sidecar(&absolute_file_path) {
+                return handle_checksums(&ctx, &user, sidecar).await;
+            }
+        }
+        if ctx.archive_browsing {
+            if let Some(archive) = archive::locate(&absolute_file_path, &dir) {
+                if let Some(reason) = denied(&ctx, &user, archive.file()) {
+                    return forbidden(archive.file(), &reason);
+                }
+                let requested = paths::relativize(&absolute_file_path, Path::new(&dir));
+                return handle_archive(&ctx, &user, client.ip, archive, &requested).await;
+            }
+        }
         info!("404 File not found");
+        not_found!()
+    }
+}
+
+/// `?ref=` browsing, only where the working tree could be opened: history is
+/// not a way around is_safe, POSIX_PERMISSIONS or LABEL_XATTR. The path may be
+/// gone from disk, so its nearest existing ancestor is what gets checked.
+/// Downloads from history count as transfers like any other.
+async fn handle_git(
+    ctx: &Context,
+    user: &AuthenticatedUser,
+    client: IpAddr,
+    file_path: &Path,
+    rev: &str,
+) -> Response<Body> {
+    let Some(existing) = file_path.ancestors().find(|p| p.exists()) else {
+        return not_found!();
+    };
+    if !is_safe(&existing.to_path_buf(), &user.directory) {
+        warn!("404 Ignored due to malicious request: {}", file_path.display());
         return not_found!();
     }
+    if let Some(reason) = denied(ctx, user, existing) {
+        return forbidden(existing, &reason);
+    }
+    let requested = paths::relativize(file_path, Path::new(&user.directory));
+    let lookup = {
+        let (file_path, base_dir, rev) = (file_path.to_path_buf(), user.directory.clone(), String::from(rev));
+        tokio::task::spawn_blocking(move || git::lookup(&file_path, &base_dir, &rev)).await.unwrap()
+    };
+    match lookup {
+        Lookup::Blob(blob) => {
+            let transfer = match ctx.transfers.start(user, &requested.to_string_lossy(), client) {
+                Ok(t) => t,
+                Err(limit) => return too_many_transfers(limit),
+            };
+            info!("200 Success");
+            let body = Body::from_stream(transfers::track(blob.stream(), transfer, Some(blob.size())));
+            git::blob_response(body, file_path, blob.size())
+        }
+        Lookup::Tree { mut names, at_root } => {
+            //entries the user couldn't open in the working tree stay hidden in history too
+            names.retain(|name| {
+                let on_disk = file_path.join(name);
+                !on_disk.exists() || (is_safe(&on_disk, &user.directory) && denied(ctx, user, &on_disk).is_none())
+            });
+            info!("200 Success");
+            git::listing(&names, at_root, &requested, rev, &ctx.base_path)
+        }
+        Lookup::NotFound => not_found!(),
+    }
+}
+
+/// A generated `.sha256` or `SHA256SUMS`. Files the user couldn't download,
+/// or that a `.listing.toml` hides, are left out of a directory's sums.
+async fn handle_checksums(ctx: &Context, user: &AuthenticatedUser, sidecar: Sidecar) -> Response<Body> {
+    let files = match sidecar {
+        Sidecar::File(file) => {
+            if !is_safe(&file, &user.directory) {
+                return not_found!();
+            }
+            if let Some(reason) = denied(ctx, user, &file) {
+                return forbidden(&file, &reason);
+            }
+            vec![file]
+        }
+        Sidecar::Directory(dir) => {
+            let mut children = vec![];
+            if let Ok(entries) = dir.read_dir() {
+                children.extend(entries.flatten().map(|e| e.path()).filter(|p| p.is_file()));
+            }
+            ListingConfig::load(&dir)
+                .arrange(children)
+                .into_iter()
+                .filter(|c| is_safe(c, &user.directory) && denied(ctx, user, c).is_none())
+                .collect()
+        }
+    };
+    let mut sums = String::new();
+    for file in files {
+        let Ok(meta) = tokio::fs::metadata(&file).await else {
+            continue;
+        };
+        if let Some(hash) = ctx.etags.sha256(&file, &meta).await {
+            sums.push_str(&checksums::line(&hash, &file));
+        }
+    }
+    info!("200 Success (generated checksums)");
+    checksums::response(sums)
+}
+
+/// Why the user may not open `path` under POSIX_PERMISSIONS or LABEL_XATTR,
+/// if they may not.
+fn denied(ctx: &Context, user: &AuthenticatedUser, path: &Path) -> Option<String> {
+    let base = Path::new(&user.directory);
+    if ctx.posix_permissions && !posix::allowed(path, base, &user.ownership) {
+        return Some(String::from("file permissions"));
+    }
+    let label = ctx.labels.as_ref()?.denied(path, base, &user.clearance)?;
+    Some(format!("label {label}"))
+}
+
+fn forbidden(path: &Path, reason: &str) -> Response<Body> {
+    warn!("403 Denied by {reason}: {}", path.display());
+    Response::builder()
+        .status(403)
+        .body("Forbidden".into())
+        .unwrap()
+}
+
+fn redirect(status: StatusCode, path: &str, query: Option<&str>) -> Response<Body> {
+    let location = match query {
+        Some(q) => format!("{path}?{q}"),
+        None => String::from(path),
+    };
+    Response::builder()
+        .status(status)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn handle_preview(previews: &Previews, file_path: &Path, transfer: TransferGuard) -> Response<Body> {
+    let meta = match tokio::fs::metadata(file_path).await {
+        Ok(m) => m,
+        Err(e) => {
+            debug!("{e}");
+            return not_found!();
+        }
+    };
+    match previews.pdf(file_path, &meta).await {
+        Ok(pdf) => match File::open(&pdf).await {
+            Ok(f) => {
+                info!("200 Success (preview)");
+                preview::preview_response(f, file_path, transfer)
+            }
+            Err(e) => {
+                debug!("{e}");
+                not_found!()
+            }
+        },
+        Err(e) => {
+            warn!("502 Preview conversion failed: {e}");
+            Response::builder()
+                .status(502)
+                .body("Preview conversion failed".into())
+                .unwrap()
+        }
+    }
+}
+
+fn too_many_transfers(limit: LimitReached) -> Response<Body> {
+    warn!("429 {limit}");
+    Response::builder()
+        .status(429)
+        .header(header::RETRY_AFTER, limit.retry_after())
+        .body("Too Many Requests".into())
+        .unwrap()
+}
+
+async fn handle_archive(
+    ctx: &Context,
+    user: &AuthenticatedUser,
+    client: IpAddr,
+    archive: ArchivePath,
+    requested: &Path,
+) -> Response<Body> {
+    let (archive, entry) = tokio::task::spawn_blocking(move || {
+        let entry = archive.entry();
+        (archive, entry)
+    })
+    .await
+    .unwrap();
+    match entry {
+        Ok(Some(Entry::Dir(children))) => {
+            info!("200 Success");
+            archive::listing(&children, requested, &ctx.base_path)
+        }
+        Ok(Some(Entry::File(len))) => {
+            let transfer = match ctx.transfers.start(user, &requested.to_string_lossy(), client) {
+                Ok(t) => t,
+                Err(limit) => return too_many_transfers(limit),
+            };
+            info!("200 Success");
+            let body = Body::from_stream(transfers::track(archive.stream(), transfer, Some(len)));
+            archive::member_response(body, archive.member(), len)
+        }
+        Ok(None) => {
+            info!("404 Not found in archive");
+            not_found!()
+        }
+        Err(e) => {
+            debug!("{e}");
+            not_found!()
+        }
+    }
 }
 
 fn is_safe(path: &PathBuf, base_dir: &str) -> bool {
@@ -122,89 +726,427 @@ fn is_safe(path: &PathBuf, base_dir: &str) -> bool {
         warn!("Potential path traversal");
         return false;
     }
+    //canonicalize both sides so Windows verbatim prefixes (\\?\C:\), drive letter
+    //case and symlinked base directories compare equal
+    let base_dir = match canonicalize(base_dir) {
+        Ok(b) => b,
+        Err(e) => {
+            debug!("{e}");
+            return false;
+        }
+    };
     match canonicalize(path) {
         Ok(true_path) => {
-            if true_path.starts_with(Path::new(base_dir)) {
-                return true;
+            if true_path.starts_with(&base_dir) {
+                true
             } else {
                 warn!(
                     "found difference in requested and absolute paths (symlink shenanigans?): {} | {}",
-                    path.to_str().unwrap(),
-                    true_path.to_str().unwrap()
+                    path.display(),
+                    true_path.display()
                 );
-                return false;
+                false
             }
         }
         Err(e) => {
             debug!("{e}");
-            return false;
+            false
         }
     }
 }
 
-fn handle_file(f: tokio::fs::File, file_path: PathBuf) -> Response<axum::body::Body> {
-    let stream = ReaderStream::new(f);
-    let body = axum::body::Body::from_stream(stream);
-    let filetype = mime_guess::from_path(&file_path).first_or_octet_stream();
-    let filename = file_path.file_name().unwrap().to_str().unwrap_or("file");
+/// Offset and length of the bytes a file response will carry.
+fn byte_span(len: u64, range: RangeRequest) -> (u64, u64) {
+    match range {
+        RangeRequest::Partial { start, end } => (start, end - start + 1),
+        _ => (0, len),
+    }
+}
 
-    Response::builder()
-        .status(200)
+/// Status and headers shared by GET and HEAD responses for a file.
+fn file_headers(
+    file_path: &Path,
+    etag: Option<String>,
+    cache_control: Option<HeaderValue>,
+    meta: &Metadata,
+    range: RangeRequest,
+) -> axum::http::response::Builder {
+    let len = meta.len();
+    let (start, count) = byte_span(len, range);
+    let filetype = mime_guess::from_path(file_path).first_or_octet_stream();
+    let filename = file_path.file_name().map_or("file".into(), |n| n.to_string_lossy());
+
+    let mut response = Response::builder()
+        .status(if let RangeRequest::Partial { .. } = range { 206 } else { 200 })
+        .header(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"))
         .header(
             header::CONTENT_TYPE,
             HeaderValue::from_str(filetype.essence_str()).unwrap(),
         )
         .header(
             header::CONTENT_DISPOSITION,
-            HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename)).unwrap(),
-        )
-        .body(body)
-        .unwrap()
+            content_disposition("attachment", &filename),
+        );
+    if let Some(etag) = etag {
+        response = response.header(header::ETAG, etag);
+    }
+    if let Some(modified) = etag::last_modified(meta) {
+        response = response.header(header::LAST_MODIFIED, modified);
+    }
+    if let Some(cache_control) = cache_control {
+        response = response.header(header::CACHE_CONTROL, cache_control);
+    }
+    if let RangeRequest::Partial { .. } = range {
+        response = response.header(header::CONTENT_RANGE, format!("bytes {}-{}/{len}", start, start + count - 1));
+    }
+    //lets clients show progress and notice truncated downloads
+    response.header(header::CONTENT_LENGTH, count)
 }
 
-fn handle_dir(file_path: PathBuf, base_dir: &PathBuf) -> Response<axum::body::Body> {
-    let mut children = vec![];
-    for entry in file_path.read_dir().unwrap() {
-        if let Ok(entry) = entry {
-            children.push(entry.path());
+/// `Content-Disposition` for `filename` as RFC 6266 has it: an ASCII-only
+/// `filename` for old clients, with anything that could end or confuse the
+/// quoted string replaced, and the exact name UTF-8 encoded in `filename*`.
+pub(crate) fn content_disposition(disposition: &'static str, filename: &str) -> HeaderValue {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | ';' | '%' => '_',
+            ' ' => c,
+            _ if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    let value = format!("{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}", paths::encode_attr(filename));
+    HeaderValue::from_str(&value).unwrap_or(HeaderValue::from_static(disposition))
+}
+
+async fn handle_file(
+    mut f: tokio::fs::File,
+    file_path: PathBuf,
+    transfer: TransferGuard,
+    etag: Option<String>,
+    cache_control: Option<HeaderValue>,
+    meta: Metadata,
+    range: RangeRequest,
+) -> Response<axum::body::Body> {
+    let (start, count) = byte_span(meta.len(), range);
+    if start > 0 {
+        if let Err(e) = f.seek(SeekFrom::Start(start)).await {
+            debug!("{e}");
+            return not_found!();
         }
     }
-    children.sort();
-    let mut r = String::new();
+    let stream = transfers::track(ReaderStream::new(f.take(count)), transfer, Some(count));
+    let body = axum::body::Body::from_stream(stream);
+    file_headers(&file_path, etag, cache_control, &meta, range).body(body).unwrap()
+}
+
+/// 304 response if the client's cached copy, identified by `If-None-Match`
+/// or failing that `If-Modified-Since`, is still current.
+async fn not_modified(
+    ctx: &Context,
+    file_path: &Path,
+    headers: &HeaderMap,
+    cache_control: Option<HeaderValue>,
+) -> Option<Response<Body>> {
+    if !headers.contains_key(header::IF_NONE_MATCH) && !headers.contains_key(header::IF_MODIFIED_SINCE) {
+        return None;
+    }
+    let meta = tokio::fs::metadata(file_path).await.ok()?;
+    let etag = ctx.etags.etag(file_path, &meta).await;
+    let fresh = match headers.get(header::IF_NONE_MATCH) {
+        Some(if_none_match) => etag.as_deref().is_some_and(|e| etag::if_none_match(if_none_match, e)),
+        None => headers
+            .get(header::IF_MODIFIED_SINCE)
+            .is_some_and(|since| etag::not_modified_since(since, &meta)),
+    };
+    if !fresh {
+        return None;
+    }
+    info!("304 Not modified");
+    let mut response = Response::builder().status(304);
+    if let Some(etag) = etag {
+        response = response.header(header::ETAG, etag);
+    }
+    if let Some(modified) = etag::last_modified(&meta) {
+        response = response.header(header::LAST_MODIFIED, modified);
+    }
+    if let Some(cache_control) = cache_control {
+        response = response.header(header::CACHE_CONTROL, cache_control);
+    }
+    Some(response.body(Body::empty()).unwrap())
+}
+
+/// Same headers as a GET, without opening the file or counting a transfer.
+async fn handle_head(
+    ctx: &Context,
+    file_path: &Path,
+    headers: &HeaderMap,
+    cache_control: Option<HeaderValue>,
+) -> Response<Body> {
+    let meta = match tokio::fs::metadata(file_path).await {
+        Ok(meta) => meta,
+        Err(e) => {
+            debug!("{e}");
+            return not_found!();
+        }
+    };
+    let etag = ctx.etags.etag(file_path, &meta).await;
+    let range = range::requested(headers, etag.as_deref(), &meta);
+    if range == RangeRequest::Unsatisfiable {
+        info!("416 Range not satisfiable");
+        return range::not_satisfiable(meta.len());
+    }
+    info!("200 Success");
+    file_headers(file_path, etag, cache_control, &meta, range).body(Body::empty()).unwrap()
+}
+
+/// Links are prefixed with `base_path`. Entries that aren't `visible` to the
+/// user are left out; `labels` shows the sensitivity label of the rest next
+/// to them.
+fn handle_dir(
+    file_path: PathBuf,
+    base_dir: &Path,
+    base_path: &str,
+    html: bool,
+    visible: &dyn Fn(&Path) -> bool,
+    labels: Option<&Labels>,
+) -> Result<Response<axum::body::Body>, RequestError> {
+    let mut children = vec![];
+    for entry in file_path.read_dir()?.flatten() {
+        children.push(entry.path());
+    }
+    children.retain(|c| visible(c));
+    let config = ListingConfig::load(&file_path);
+    let children = config.arrange(children);
+    if !html {
+        return Ok(text_listing(&children, base_dir, base_path));
+    }
+    let mut r = directory_header(&file_path, config.releases());
 
     //parent dir link
-    let dir = remove_base_dir(file_path, base_dir);
+    let dir = paths::relativize(&file_path, base_dir);
     if let Some(parent) = dir.parent() {
-        r.push_str(html_link(parent).as_str());
+        let mut parent = parent.to_path_buf();
+        parent.push("");
+        r.push_str(html_link(base_path, &parent).as_str());
         r.push_str("<br>\n");
     }
 
     for c in children {
-        let p = remove_base_dir(c, &base_dir);
-        r.push_str(html_link(&p).as_str());
+        let mut p = paths::relativize(&c, base_dir);
+        if c.is_dir() {
+            //link straight to the canonical form instead of through a redirect
+            p.push("");
+        }
+        r.push_str(html_link(base_path, &p).as_str());
+        if let Some(label) = labels.and_then(|l| l.label(&c)) {
+            r.push_str(&format!(" [{}]", feed::xml_escape(&label)));
+        }
         r.push_str("<br>\n");
     }
-    let body = Body::from(r);
-    Response::builder().status(200).body(body).unwrap()
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))
+        .header(header::VARY, HeaderValue::from_static("Accept"))
+        .body(Body::from(r))
+        .unwrap())
 }
 
-fn remove_base_dir(path: PathBuf, base: &PathBuf) -> PathBuf {
-    let new_path = path
-        .to_str()
-        .unwrap()
-        .split_once(base.to_str().unwrap())
+/// Newline-separated, percent-encoded paths of a directory's entries, ready to
+/// append to the server URL; directories end in `/`.
+fn text_listing(children: &[PathBuf], base_dir: &Path, base_path: &str) -> Response<Body> {
+    let mut r = String::new();
+    for c in children {
+        r.push_str(base_path);
+        r.push_str(&paths::encode(&paths::relativize(c, base_dir)));
+        if c.is_dir() {
+            r.push('/');
+        }
+        r.push('\n');
+    }
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"))
+        .header(header::VARY, HeaderValue::from_static("Accept"))
+        .body(Body::from(r))
         .unwrap()
-        .1;
-    PathBuf::from(new_path)
 }
 
-fn html_link(pb: &Path) -> String {
-    let mut s = pb.to_str().unwrap();
-    let mut href = String::new();
-    href.push_str(s);
-    if s == "" {
-        s = ".."
+/// HEADER.html (verbatim) and README.md (rendered) from a directory, shown
+/// above its listing so a shared folder can describe itself. A releases
+/// directory shows its changelog first.
+fn directory_header(dir: &Path, releases: bool) -> String {
+    let mut r = String::new();
+    if let Ok(header) = std::fs::read_to_string(dir.join("HEADER.html")) {
+        r.push_str(&header);
+    }
+    let changelog = releases
+        .then(|| listing::CHANGELOGS.iter().find_map(|c| std::fs::read_to_string(dir.join(c)).ok()))
+        .flatten();
+    for markdown in changelog.iter().chain(std::fs::read_to_string(dir.join("README.md")).ok().iter()) {
+        let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::ENABLE_TABLES);
+        pulldown_cmark::html::push_html(&mut r, parser);
+    }
+    if !r.is_empty() {
+        r.push_str("<hr>\n");
+    }
+    r
+}
+
+/// Whether the client asked for HTML, as browsers do, rather than e.g. curl's `*/*`.
+pub(crate) fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| a.contains("text/html"))
+}
+
+fn html_link(base_path: &str, pb: &Path) -> String {
+    let href = format!("{base_path}{}", paths::encode(pb));
+    let mut s = pb.to_string_lossy();
+    if s.is_empty() {
+        s = "..".into()
+    }
+
+    format!("<a href=\"{href}\">{}</a>", feed::xml_escape(&s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed again on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = env::temp_dir().join(format!("fileserver-test-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn is_safe_accepts_paths_below_base() {
+        let tmp = TempDir::new("below");
+        std::fs::write(tmp.0.join("a.txt"), "a").unwrap();
+        let base = tmp.0.to_str().unwrap();
+        assert!(is_safe(&tmp.0.join("a.txt"), base));
+        assert!(is_safe(&tmp.0, base));
+        assert!(!is_safe(&tmp.0.join("missing.txt"), base));
+        assert!(!is_safe(&tmp.0.join("..").join(tmp.0.file_name().unwrap()).join("a.txt"), base));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_safe_follows_symlinked_base() {
+        let tmp = TempDir::new("symlinked-base");
+        let real = tmp.0.join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::fs::write(real.join("a.txt"), "a").unwrap();
+        let link = tmp.0.join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let base = link.to_str().unwrap();
+        assert!(is_safe(&link.join("a.txt"), base));
+        assert!(is_safe(&real.join("a.txt"), base));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_safe_rejects_symlinks_out_of_base() {
+        let tmp = TempDir::new("symlink-out");
+        let base = tmp.0.join("base");
+        std::fs::create_dir(&base).unwrap();
+        std::fs::write(tmp.0.join("outside.txt"), "x").unwrap();
+        std::os::unix::fs::symlink(tmp.0.join("outside.txt"), base.join("escape")).unwrap();
+        let link = tmp.0.join("link");
+        std::os::unix::fs::symlink(&base, &link).unwrap();
+        assert!(!is_safe(&base.join("escape"), base.to_str().unwrap()));
+        assert!(!is_safe(&link.join("escape"), link.to_str().unwrap()));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn is_safe_ignores_verbatim_prefix() {
+        let tmp = TempDir::new("verbatim");
+        std::fs::write(tmp.0.join("a.txt"), "a").unwrap();
+        let verbatim = canonicalize(&tmp.0).unwrap();
+        assert!(verbatim.to_str().unwrap().starts_with(r"\\?\"));
+        assert!(is_safe(&verbatim.join("a.txt"), tmp.0.to_str().unwrap()));
+        assert!(is_safe(&tmp.0.join("a.txt"), verbatim.to_str().unwrap()));
     }
 
-    format!("<a href=\"{href}\">{s}</a>")
+    #[cfg(windows)]
+    #[test]
+    fn is_safe_ignores_drive_letter_case() {
+        let tmp = TempDir::new("drive-case");
+        std::fs::write(tmp.0.join("a.txt"), "a").unwrap();
+        let base = tmp.0.to_str().unwrap();
+        //C:\Users\... => c:\Users\...
+        let (drive, rest) = base.split_at(1);
+        let flipped = if drive == drive.to_ascii_uppercase() {
+            format!("{}{rest}", drive.to_ascii_lowercase())
+        } else {
+            format!("{}{rest}", drive.to_ascii_uppercase())
+        };
+        assert!(is_safe(&PathBuf::from(&flipped).join("a.txt"), base));
+        assert!(is_safe(&tmp.0.join("a.txt"), &flipped));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn backslash_segments_cannot_escape() {
+        let tmp = TempDir::new("backslash");
+        let base = tmp.0.to_str().unwrap();
+        assert_eq!(paths::join_checked(&tmp.0, &paths::decode(r"a\..\..\x")), None);
+        assert_eq!(paths::join_checked(&tmp.0, &paths::decode("a%5C..%5C..%5Cx")), None);
+        assert!(!is_safe(&PathBuf::from(format!(r"{base}\..\x")), base));
+    }
+
+    #[test]
+    fn content_disposition_keeps_plain_names() {
+        let value = content_disposition("attachment", "report 2024.pdf");
+        assert_eq!(
+            value.to_str().unwrap(),
+            "attachment; filename=\"report 2024.pdf\"; filename*=UTF-8''report%202024.pdf"
+        );
+    }
+
+    #[test]
+    fn content_disposition_encodes_non_ascii() {
+        let value = content_disposition("inline", "résumé ✓.pdf");
+        assert_eq!(
+            value.to_str().unwrap(),
+            "inline; filename=\"r_sum_ _.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%E2%9C%93.pdf"
+        );
+    }
+
+    #[test]
+    fn content_disposition_escapes_quoted_string_syntax() {
+        let value = content_disposition("attachment", "a\"b\\c;d%e.txt");
+        assert_eq!(
+            value.to_str().unwrap(),
+            "attachment; filename=\"a_b_c_d_e.txt\"; filename*=UTF-8''a%22b%5Cc%3Bd%25e.txt"
+        );
+    }
+
+    #[test]
+    fn content_disposition_is_always_a_valid_header() {
+        for name in ["", "\r\nSet-Cookie: x=1", "tab\there", "\u{7f}\u{0}", "日本語", "x\"; filename=\"evil.exe", "%00"] {
+            let value = content_disposition("attachment", name);
+            let text = value.to_str().unwrap();
+            assert!(text.starts_with("attachment; filename=\""), "{text}");
+            //exactly the two parameters, however hostile the name
+            assert_eq!(text.matches(';').count(), 2, "{text}");
+            assert!(!text.contains(['\r', '\n', '\u{0}']), "{text}");
+        }
+    }
 }
@@ -0,0 +1,18 @@
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::{body::Body, response::Response};
+
+/// `Allow` for routes registered with `get`, which also serves HEAD.
+pub(crate) const GET: &str = "GET,HEAD,OPTIONS";
+pub(crate) const DELETE: &str = "DELETE,OPTIONS";
+
+/// Answers OPTIONS with the methods a route supports, so WebDAV-style clients
+/// probing the server get a 204 and `Allow` instead of an error. Other
+/// unsupported methods already get the router's 405, whose `Allow` lists
+/// OPTIONS once a route registers this handler.
+pub(crate) async fn options(allow: &'static str) -> Response {
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(header::ALLOW, HeaderValue::from_static(allow))
+        .body(Body::empty())
+        .unwrap()
+}
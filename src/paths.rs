@@ -0,0 +1,258 @@
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+/// Bytes escaped in generated hrefs. `/` is kept so links stay readable, and
+/// every non-ASCII byte is escaped, which keeps non-UTF-8 names intact.
+const HREF: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'&')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// HREF plus what would alter a query parameter value.
+const QUERY: &AsciiSet = &HREF.add(b'+').add(b'=');
+
+/// Everything but RFC 8187's attr-char, for `filename*` header parameters.
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Lexically normalizes a path meant to stay below some base: `.` is dropped
+/// and `None` is returned for anything that could leave the base (`..`, a
+/// root or a drive prefix). A trailing `/` is kept, since it marks a request
+/// for a directory (or an archive's contents).
+pub(crate) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if to_bytes(path).last() == Some(&b'/') && !normalized.as_os_str().is_empty() {
+        normalized.push("");
+    }
+    Some(normalized)
+}
+
+/// A raw URL path with runs of `/` collapsed, or `None` if it has none.
+pub(crate) fn collapse_slashes(path: &str) -> Option<String> {
+    if !path.contains("//") {
+        return None;
+    }
+    let mut collapsed = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !collapsed.ends_with('/') {
+            collapsed.push(c);
+        }
+    }
+    Some(collapsed)
+}
+
+/// `base` joined with `relative`, or `None` if `relative` would escape it.
+pub(crate) fn join_checked(base: &Path, relative: &Path) -> Option<PathBuf> {
+    let relative = normalize(relative)?;
+    if relative.as_os_str().is_empty() {
+        return Some(base.to_path_buf());
+    }
+    Some(base.join(relative))
+}
+
+/// The part of `path` below `base` as a URL path, with `/` separators on
+/// every platform. The inverse of `join_checked`.
+pub(crate) fn relativize(path: &Path, base: &Path) -> PathBuf {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    let mut url = OsString::new();
+    for c in relative.components() {
+        if let Component::Normal(name) = c {
+            url.push("/");
+            url.push(name);
+        }
+    }
+    PathBuf::from(url)
+}
+
+/// Percent-encodes a URL path built from file names, byte for byte.
+pub(crate) fn encode(path: &Path) -> String {
+    percent_encode(&to_bytes(path), HREF).to_string()
+}
+
+/// Percent-encodes a value for use in a query string.
+pub(crate) fn encode_query(value: &str) -> String {
+    percent_encode(value.as_bytes(), QUERY).to_string()
+}
+
+/// Percent-encodes a header parameter value as UTF-8, for `filename*=UTF-8''...`.
+pub(crate) fn encode_attr(value: &str) -> String {
+    percent_encode(value.as_bytes(), ATTR_CHAR).to_string()
+}
+
+/// Decodes a request path byte for byte, so names that aren't valid UTF-8
+/// can still be requested.
+pub(crate) fn decode(path: &str) -> PathBuf {
+    from_bytes(percent_decode_str(path).collect())
+}
+
+#[cfg(unix)]
+fn to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+pub(crate) fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+//names on other platforms are (potentially ill-formed) UTF-16, only UTF-8 round-trips
+#[cfg(not(unix))]
+fn to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> PathBuf {
+        PathBuf::from("/srv/files")
+    }
+
+    #[test]
+    fn normalize_drops_current_dir() {
+        assert_eq!(normalize(Path::new("./a/./b")), Some(PathBuf::from("a/b")));
+        assert_eq!(normalize(Path::new("")), Some(PathBuf::new()));
+        assert_eq!(normalize(Path::new("./")), Some(PathBuf::new()));
+    }
+
+    #[test]
+    fn normalize_rejects_escapes() {
+        for path in ["..", "../a", "a/../../b", "a/..", "/etc/passwd", "/"] {
+            assert_eq!(normalize(Path::new(path)), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn normalize_keeps_trailing_slash() {
+        assert_eq!(to_bytes(&normalize(Path::new("a/b/")).unwrap()), b"a/b/");
+        assert_eq!(to_bytes(&normalize(Path::new("a/b")).unwrap()), b"a/b");
+        assert_eq!(to_bytes(&normalize(Path::new("a//b/./")).unwrap()), b"a/b/");
+    }
+
+    #[test]
+    fn join_checked_stays_below_base() {
+        assert_eq!(join_checked(&base(), Path::new("a/b.txt")), Some(base().join("a/b.txt")));
+        assert_eq!(join_checked(&base(), Path::new("")), Some(base()));
+        assert_eq!(join_checked(&base(), Path::new("../files2/x")), None);
+        assert_eq!(join_checked(&base(), Path::new("/etc/passwd")), None);
+    }
+
+    #[test]
+    fn decoded_traversal_is_rejected() {
+        for raw in ["%2e%2e", "%2E%2E/x", "a/%2e%2e/%2e%2e", "%2F..%2F..%2Fetc", "..%2F", "%2Fetc%2Fpasswd"] {
+            assert_eq!(join_checked(&base(), &decode(raw)), None, "{raw}");
+        }
+    }
+
+    #[test]
+    fn decoded_separators_stay_below_base() {
+        assert_eq!(join_checked(&base(), &decode("a%2Fb")), Some(base().join("a/b")));
+        assert_eq!(join_checked(&base(), &decode("%2e/a")), Some(base().join("a")));
+    }
+
+    /// Every combination of a few hostile segments either joins to a path
+    /// below the base without `..` in it, or is rejected.
+    #[test]
+    fn join_checked_never_escapes() {
+        let segments = ["a", ".", "..", "", "%2e%2e", "%2e", "%2F", "b%2F..", "%00", "%FF"];
+        let mut paths = vec![String::new()];
+        for _ in 0..4 {
+            paths = paths
+                .iter()
+                .flat_map(|p| segments.iter().map(move |s| format!("{p}/{s}")))
+                .collect();
+            for raw in &paths {
+                let Some(joined) = join_checked(&base(), &decode(raw.trim_start_matches('/'))) else {
+                    continue;
+                };
+                assert!(joined.starts_with(base()), "{raw} => {}", joined.display());
+                assert!(!joined.components().any(|c| c == Component::ParentDir), "{raw}");
+            }
+        }
+    }
+
+    #[test]
+    fn relativize_is_the_inverse_of_join_checked() {
+        let joined = join_checked(&base(), Path::new("a/b.txt")).unwrap();
+        assert_eq!(relativize(&joined, &base()), PathBuf::from("/a/b.txt"));
+        assert_eq!(relativize(&base(), &base()), PathBuf::new());
+        assert_eq!(relativize(&base().join("a/"), &base()), PathBuf::from("/a"));
+    }
+
+    #[test]
+    fn relativize_compares_whole_components() {
+        //"/srv/files" is a string prefix of "/srv/filesx" but not its parent
+        let sibling = Path::new("/srv/filesx/a");
+        assert_eq!(relativize(sibling, &base()), PathBuf::from("/srv/filesx/a"));
+    }
+
+    #[test]
+    fn encode_escapes_link_syntax() {
+        assert_eq!(encode(Path::new("/a b/#1?&x.txt")), "/a%20b/%231%3F%26x.txt");
+        assert_eq!(encode_query("v1.0+rc=1"), "v1.0%2Brc%3D1");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn relativize_uses_forward_slashes() {
+        let base = Path::new(r"C:\srv\files");
+        assert_eq!(relativize(Path::new(r"C:\srv\files\a\b.txt"), base), PathBuf::from("/a/b.txt"));
+        assert_eq!(encode(&relativize(Path::new(r"C:\srv\files\a b\c"), base)), "/a%20b/c");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn normalize_rejects_drive_and_unc_prefixes() {
+        for path in [r"C:\Windows", r"C:Windows", r"\\server\share\x", r"\\?\C:\x", r"a\..\..\b"] {
+            assert_eq!(normalize(Path::new(path)), None, "{path}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip() {
+        let path = decode("dir/%FF%FEname.txt");
+        assert_eq!(to_bytes(&path), b"dir/\xff\xfename.txt");
+        let joined = join_checked(&base(), &path).unwrap();
+        let url = relativize(&joined, &base());
+        assert_eq!(encode(&url), "/dir/%FF%FEname.txt");
+        assert_eq!(decode(encode(&url).trim_start_matches('/')), path);
+    }
+}
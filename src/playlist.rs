@@ -0,0 +1,53 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use mime_guess::mime;
+use std::path::{Path, PathBuf};
+
+use crate::listing::ListingConfig;
+use crate::{content_disposition, paths};
+use crate::error::RequestError;
+
+fn is_audio(path: &Path) -> bool {
+    mime_guess::from_path(path)
+        .first()
+        .is_some_and(|m| m.type_() == mime::AUDIO)
+}
+
+/// M3U playlist of the audio files in a directory, in listing order, so a
+/// folder can be streamed straight from VLC or a browser player. Files that
+/// aren't `visible` to the user are left out.
+pub(crate) fn handle_playlist(
+    file_path: PathBuf,
+    base_dir: &Path,
+    origin: &str,
+    visible: &dyn Fn(&Path) -> bool,
+) -> Result<Response<Body>, RequestError> {
+    let mut children = vec![];
+    for entry in file_path.read_dir()?.flatten() {
+        let path = entry.path();
+        if path.is_file() && is_audio(&path) && visible(&path) {
+            children.push(path);
+        }
+    }
+    let children = ListingConfig::load(&file_path).arrange(children);
+
+    let mut r = String::from("#EXTM3U\n");
+    for c in children {
+        let title = c.file_stem().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let p = paths::relativize(&c, base_dir);
+        r.push_str(&format!("#EXTINF:-1,{title}\n{origin}{}\n", paths::encode(&p)));
+    }
+
+    let name = file_path.file_name().map_or("playlist".into(), |n| n.to_string_lossy());
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("audio/x-mpegurl; charset=utf-8"))
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("attachment", &format!("{name}.m3u8")),
+        )
+        .body(Body::from(r))
+        .unwrap())
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+
+use crate::provision::Ownership;
+
+/// Whether a user may open `path` according to the owner/group/other mode
+/// bits on disk, taking them to be the system user `ownership.uid` in groups
+/// `gid` and `groups`: every directory from `base` down must be searchable,
+/// and the target (`base` itself, for the root listing) readable (and
+/// searchable, for a directory). Users without a uid only get the "other"
+/// bits. Lets existing filesystem permissions be honored instead of
+/// duplicated in users.json.
+#[cfg(unix)]
+pub(crate) fn allowed(path: &Path, base: &Path, ownership: &Ownership) -> bool {
+    let Ok(relative) = path.strip_prefix(base) else {
+        return false;
+    };
+    let mut dir = base.to_path_buf();
+    //the base itself is the last directory when it's what's being listed
+    let wanted = if relative.as_os_str().is_empty() { READ | SEARCH } else { SEARCH };
+    if !permits(&dir, ownership, wanted) {
+        return false;
+    }
+    let mut components = relative.components().peekable();
+    while let Some(c) = components.next() {
+        dir.push(c);
+        let is_last = components.peek().is_none();
+        let wanted = match (is_last, dir.is_dir()) {
+            (false, _) => SEARCH,
+            (true, true) => READ | SEARCH,
+            (true, false) => READ,
+        };
+        if !permits(&dir, ownership, wanted) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(not(unix))]
+pub(crate) fn allowed(_path: &Path, _base: &Path, _ownership: &Ownership) -> bool {
+    true
+}
+
+#[cfg(unix)]
+const READ: u32 = 0o4;
+#[cfg(unix)]
+const SEARCH: u32 = 0o1;
+
+/// Checks only the class of bits that applies, as the kernel does: an owner
+/// denied by the owner bits isn't let in by the group or other bits.
+#[cfg(unix)]
+fn permits(path: &Path, ownership: &Ownership, wanted: u32) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let Ok(meta) = std::fs::metadata(path) else {
+        return false;
+    };
+    let mode = meta.mode();
+    let bits = if ownership.uid == Some(meta.uid()) {
+        mode >> 6
+    } else if ownership.gid == Some(meta.gid()) || ownership.groups.contains(&meta.gid()) {
+        mode >> 3
+    } else {
+        mode
+    };
+    bits & wanted == wanted
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::PathBuf;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("fileserver-posix-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::set_permissions(&self.0, std::fs::Permissions::from_mode(0o755));
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn other() -> Ownership {
+        Ownership::default()
+    }
+
+    #[test]
+    fn searchable_base_is_not_listable() {
+        let tmp = TempDir::new("711");
+        std::fs::write(tmp.0.join("a.txt"), "a").unwrap();
+        std::fs::set_permissions(tmp.0.join("a.txt"), std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::set_permissions(&tmp.0, std::fs::Permissions::from_mode(0o711)).unwrap();
+        //files below can still be opened by name, but the base can't be listed
+        assert!(allowed(&tmp.0.join("a.txt"), &tmp.0, &other()));
+        assert!(!allowed(&tmp.0, &tmp.0, &other()));
+        let owner = Ownership {
+            uid: Some(std::fs::metadata(&tmp.0).unwrap().uid()),
+            ..Ownership::default()
+        };
+        assert!(allowed(&tmp.0, &tmp.0, &owner));
+    }
+
+    #[test]
+    fn every_directory_on_the_way_must_be_searchable() {
+        let tmp = TempDir::new("search");
+        let sub = tmp.0.join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(sub.join("a.txt"), "a").unwrap();
+        std::fs::set_permissions(sub.join("a.txt"), std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::fs::set_permissions(&sub, std::fs::Permissions::from_mode(0o754)).unwrap();
+        std::fs::set_permissions(&tmp.0, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(!allowed(&sub.join("a.txt"), &tmp.0, &other()));
+        assert!(!allowed(&sub, &tmp.0, &other()));
+    }
+}
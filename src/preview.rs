@@ -0,0 +1,112 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use reqwest::multipart::{Form, Part};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::fs::{self, File};
+use tokio_util::io::ReaderStream;
+
+use crate::content_disposition;
+use crate::status::CacheStats;
+use crate::transfers::{self, TransferGuard};
+
+const OFFICE_EXTENSIONS: &[&str] = &[
+    "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "odp", "rtf",
+];
+
+pub(crate) fn is_office(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| OFFICE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Renders office documents to PDF through a Gotenberg-compatible converter
+/// (LibreOffice route) and keeps the results in a cache directory, keyed by
+/// path, size and mtime so edits produce a fresh preview.
+pub(crate) struct Previews {
+    client: reqwest::Client,
+    converter_url: String,
+    cache_dir: PathBuf,
+    stats: CacheStats,
+}
+
+impl Previews {
+    pub(crate) fn new(converter_url: String, cache_dir: PathBuf) -> Self {
+        std::fs::create_dir_all(&cache_dir).expect("Failed to create preview cache directory");
+        Previews {
+            client: reqwest::Client::new(),
+            converter_url: String::from(converter_url.trim_end_matches('/')),
+            cache_dir,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    pub(crate) async fn pdf(&self, path: &Path, meta: &Metadata) -> Result<PathBuf, String> {
+        let cached = self.cache_dir.join(format!("{}.pdf", cache_key(path, meta)));
+        if fs::try_exists(&cached).await.unwrap_or(false) {
+            self.stats.hit();
+            return Ok(cached);
+        }
+        self.stats.miss();
+
+        let data = fs::read(path).await.map_err(|e| e.to_string())?;
+        let filename = path.file_name().map_or("document".into(), |n| n.to_string_lossy());
+        let form = Form::new().part("files", Part::bytes(data).file_name(String::from(filename)));
+        let response = self
+            .client
+            .post(format!("{}/forms/libreoffice/convert", self.converter_url))
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("converter returned {}", response.status()));
+        }
+        let pdf = response.bytes().await.map_err(|e| e.to_string())?;
+
+        //write under a unique name first so concurrent conversions never serve a partial file
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let tmp = cached.with_extension(format!("{nanos}.tmp"));
+        fs::write(&tmp, &pdf).await.map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &cached).await.map_err(|e| e.to_string())?;
+        Ok(cached)
+    }
+}
+
+fn cache_key(path: &Path, meta: &Metadata) -> String {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    hasher.update(meta.len().to_le_bytes());
+    hasher.update(mtime.to_le_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+pub(crate) fn preview_response(f: File, original: &Path, transfer: TransferGuard) -> Response<Body> {
+    let stream = transfers::track(ReaderStream::new(f), transfer, None);
+    let stem = original.file_stem().map_or("preview".into(), |n| n.to_string_lossy());
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("application/pdf"))
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("inline", &format!("{stem}.pdf")),
+        )
+        .body(Body::from_stream(stream))
+        .unwrap()
+}
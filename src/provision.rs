@@ -0,0 +1,143 @@
+use log::{error, info};
+use serde::Deserialize;
+use std::{
+    fs::{self, DirBuilder},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::auth::AuthenticatedUser;
+
+/// Mode for directories created on first login when DIRECTORY_MODE is unset.
+const DEFAULT_DIRECTORY_MODE: u32 = 0o750;
+
+/// Creates a user's directory the first time they log in if it is missing,
+/// instead of serving 404s until an admin runs mkdir. The new directory is
+/// populated from the user's skeleton tree, or SKELETON_DIR if they have none.
+pub(crate) struct Provisioning {
+    mode: u32,
+    skeleton: Option<PathBuf>,
+}
+
+/// Per-user permissions and (when running as root) ownership for everything
+/// the server creates in a user's directory, so other processes on the host
+/// can read it. Modes are octal strings such as `"750"`. With POSIX_PERMISSIONS
+/// the uid, gid and supplementary `groups` are also who the user is checked as.
+#[derive(Clone, Default, Deserialize)]
+pub(crate) struct Ownership {
+    pub(crate) directory_mode: Option<String>,
+    pub(crate) file_mode: Option<String>,
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    #[serde(default)]
+    pub(crate) groups: Vec<u32>,
+}
+
+/// Modes and owner resolved for one user.
+struct Apply {
+    directory_mode: u32,
+    file_mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl Provisioning {
+    pub(crate) fn from_env(
+        enabled: Option<String>,
+        mode: Option<String>,
+        skeleton: Option<String>,
+    ) -> Option<Provisioning> {
+        if enabled.as_deref() != Some("true") {
+            return None;
+        }
+        let mode = mode
+            .map(|m| parse_mode(&m).expect("Invalid Env var: DIRECTORY_MODE"))
+            .unwrap_or(DEFAULT_DIRECTORY_MODE);
+        Some(Provisioning {
+            mode,
+            skeleton: skeleton.map(PathBuf::from),
+        })
+    }
+
+    pub(crate) fn ensure(&self, user: &AuthenticatedUser) {
+        let dir = Path::new(&user.directory);
+        if dir.is_dir() {
+            return;
+        }
+        let ownership = &user.ownership;
+        let apply = Apply {
+            directory_mode: ownership.directory_mode.as_deref().and_then(parse_mode).unwrap_or(self.mode),
+            file_mode: ownership.file_mode.as_deref().and_then(parse_mode),
+            uid: ownership.uid,
+            gid: ownership.gid,
+        };
+        if let Err(e) = create(dir, &apply) {
+            error!("Failed to create directory {} for {}: {e}", dir.display(), user.username);
+            return;
+        }
+        info!("Created directory {} for {}", dir.display(), user.username);
+        let skeleton = user.skeleton.as_deref().map(Path::new).or(self.skeleton.as_deref());
+        if let Some(skeleton) = skeleton {
+            if let Err(e) = copy_tree(skeleton, dir, &apply) {
+                error!("Failed to copy skeleton {} for {}: {e}", skeleton.display(), user.username);
+            }
+        }
+    }
+}
+
+/// Copies files and directories; symlinks are skipped so a skeleton can't
+/// expose anything outside itself.
+fn copy_tree(from: &Path, to: &Path, apply: &Apply) -> io::Result<()> {
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            create(&target, apply)?;
+            copy_tree(&entry.path(), &target, apply)?;
+        } else if kind.is_file() {
+            fs::copy(entry.path(), &target)?;
+            if let Some(mode) = apply.file_mode {
+                set_mode(&target, mode)?;
+            }
+            set_owner(&target, apply)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parses an octal permission mode such as `750` or `0750`.
+pub(crate) fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o7777)
+}
+
+fn create(dir: &Path, apply: &Apply) -> io::Result<()> {
+    DirBuilder::new().recursive(true).create(dir)?;
+    //set explicitly rather than through the builder, which applies the umask
+    set_mode(dir, apply.directory_mode)?;
+    set_owner(dir, apply)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(unix)]
+fn set_owner(path: &Path, apply: &Apply) -> io::Result<()> {
+    if apply.uid.is_none() && apply.gid.is_none() {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(path, apply.uid, apply.gid)
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_owner(_path: &Path, _apply: &Apply) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, HeaderName};
+use axum::{middleware::Next, response::Response};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Reverse proxies whose Forwarded / X-Forwarded-* headers are believed, from
+/// TRUSTED_PROXIES: a comma separated list of addresses and CIDR ranges such as
+/// `127.0.0.1,10.0.0.0/8,::1`. Headers from anyone else are ignored, since
+/// clients can send them too.
+pub(crate) struct Proxies {
+    trusted: Vec<(IpAddr, u8)>,
+    scheme: &'static str,
+}
+
+/// Who sent a request, as far as trusted proxies say: the client's address,
+/// and the scheme and host it addressed, like `https://files.example.com`,
+/// for links that leave the page (feeds, playlists).
+#[derive(Clone)]
+pub(crate) struct Client {
+    pub(crate) ip: IpAddr,
+    pub(crate) origin: String,
+}
+
+/// One proxy hop: who it got the request from, and how.
+#[derive(Default)]
+struct Hop {
+    client: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+impl Proxies {
+    /// `scheme` is what clients use when no trusted proxy says otherwise.
+    pub(crate) fn from_env(trusted: Option<String>, scheme: &'static str) -> Result<Proxies, String> {
+        let trusted = match trusted {
+            Some(list) => list
+                .split(',')
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .map(|p| network(p).ok_or(format!("TRUSTED_PROXIES: {p} is not an address or CIDR range")))
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        Ok(Proxies { trusted, scheme })
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.trusted.iter().any(|(net, len)| contains(*net, *len, ip))
+    }
+
+    /// The client's address, scheme and host. Hops are walked from the nearest
+    /// proxy outwards, stopping at the first address that isn't trusted.
+    fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> (IpAddr, String, Option<String>) {
+        let mut client = peer;
+        let mut scheme = String::from(self.scheme);
+        let mut host = None;
+        if self.trusts(peer) {
+            for hop in hops(headers).into_iter().rev() {
+                let Some(ip) = hop.client else {
+                    break;
+                };
+                client = ip;
+                if let Some(proto) = hop.proto {
+                    scheme = proto;
+                }
+                host = hop.host.or(host);
+                if !self.trusts(ip) {
+                    break;
+                }
+            }
+        }
+        (client, scheme, host)
+    }
+}
+
+/// Records the `Client` behind a request, so logs, transfer listings and
+/// generated links use the client's view rather than the proxy's.
+pub(crate) async fn forwarded(State(proxies): State<Arc<Proxies>>, mut req: Request, next: Next) -> Response {
+    let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+    let (client, scheme, host) = match peer {
+        Some(peer) => proxies.resolve(peer.ip(), req.headers()),
+        None => (IpAddr::from([127, 0, 0, 1]), String::from(proxies.scheme), None),
+    };
+    let host = host
+        .or_else(|| req.headers().get(header::HOST).and_then(|h| h.to_str().ok()).map(String::from))
+        .unwrap_or(String::from("localhost"));
+    req.extensions_mut().insert(Client {
+        ip: client,
+        origin: format!("{scheme}://{host}"),
+    });
+    next.run(req).await
+}
+
+/// Hops from `Forwarded` if present, otherwise from the X-Forwarded-* headers
+/// lined up by position, outermost first.
+fn hops(headers: &HeaderMap) -> Vec<Hop> {
+    let forwarded = values(headers, &header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded.iter().map(|element| hop(element)).collect();
+    }
+    let ips = values(headers, &X_FORWARDED_FOR);
+    let protos = values(headers, &X_FORWARDED_PROTO);
+    let hosts = values(headers, &X_FORWARDED_HOST);
+    //proxies append, so the lists line up from the nearest hop; one that only sets
+    //X-Forwarded-Proto leaves it shorter than X-Forwarded-For
+    let nth = |list: &[String], i: usize| list.len().checked_sub(ips.len() - i).and_then(|j| list.get(j)).cloned();
+    ips.iter()
+        .enumerate()
+        .map(|(i, ip)| Hop {
+            client: address(ip),
+            proto: nth(&protos, i).filter(|p| is_scheme(p)),
+            host: nth(&hosts, i),
+        })
+        .collect()
+}
+
+/// Comma separated values across every instance of a header.
+fn values(headers: &HeaderMap, name: &HeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| String::from(v.trim()))
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+/// One `Forwarded` element, e.g. `for="[2001:db8::1]:4711";proto=https;host=example.com`.
+fn hop(element: &str) -> Hop {
+    let mut hop = Hop::default();
+    for pair in element.split(';') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches('"');
+        match key.trim().to_ascii_lowercase().as_str() {
+            "for" => hop.client = address(value),
+            "proto" if is_scheme(value) => hop.proto = Some(value.to_ascii_lowercase()),
+            "host" => hop.host = Some(String::from(value)),
+            _ => {}
+        }
+    }
+    hop
+}
+
+fn is_scheme(proto: &str) -> bool {
+    proto.eq_ignore_ascii_case("http") || proto.eq_ignore_ascii_case("https")
+}
+
+/// An address with an optional port: `192.0.2.1`, `192.0.2.1:80`, `[::1]:80` or `::1`.
+/// Obfuscated identifiers (`unknown`, `_hidden`) give `None`.
+fn address(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .map(|ip| ip.to_canonical())
+}
+
+/// An address or CIDR range such as `10.0.0.0/8`.
+fn network(value: &str) -> Option<(IpAddr, u8)> {
+    let (ip, len) = match value.split_once('/') {
+        Some((ip, len)) => (ip.parse::<IpAddr>().ok()?, len.parse::<u8>().ok()?),
+        None => {
+            let ip = value.parse::<IpAddr>().ok()?;
+            (ip, if ip.is_ipv4() { 32 } else { 128 })
+        }
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    (len <= max).then_some((ip, len))
+}
+
+fn contains(net: IpAddr, len: u8, ip: IpAddr) -> bool {
+    match (net, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(net) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(net) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(trusted: &str) -> Proxies {
+        Proxies::from_env(Some(String::from(trusted)), "http").unwrap()
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_addresses_and_cidr_ranges() {
+        assert_eq!(network("10.0.0.0/8"), Some((ip("10.0.0.0"), 8)));
+        assert_eq!(network("127.0.0.1"), Some((ip("127.0.0.1"), 32)));
+        assert_eq!(network("::1"), Some((ip("::1"), 128)));
+        assert_eq!(network("2001:db8::/32"), Some((ip("2001:db8::"), 32)));
+        assert_eq!(network("10.0.0.0/33"), None);
+        assert_eq!(network("::/129"), None);
+        assert_eq!(network("10.0.0.0/x"), None);
+        assert_eq!(network("proxy.local"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_trusted_proxies() {
+        assert!(Proxies::from_env(Some(String::from("10.0.0.0/8, nope")), "http").is_err());
+        assert_eq!(proxies(" 10.0.0.1 ,, ::1 ").trusted.len(), 2);
+    }
+
+    #[test]
+    fn contains_masks_by_prefix_length() {
+        assert!(contains(ip("10.0.0.0"), 8, ip("10.255.1.2")));
+        assert!(!contains(ip("10.0.0.0"), 8, ip("11.0.0.1")));
+        assert!(contains(ip("0.0.0.0"), 0, ip("192.0.2.1")));
+        assert!(contains(ip("192.0.2.1"), 32, ip("192.0.2.1")));
+        assert!(!contains(ip("192.0.2.1"), 32, ip("192.0.2.2")));
+        assert!(contains(ip("2001:db8::"), 32, ip("2001:db8:ffff::1")));
+        assert!(!contains(ip("2001:db8::"), 32, ip("2001:db9::1")));
+        assert!(contains(ip("::"), 0, ip("2001:db8::1")));
+        assert!(!contains(ip("10.0.0.0"), 8, ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn headers_from_untrusted_peers_are_ignored() {
+        let h = headers(&[("x-forwarded-for", "192.0.2.7"), ("x-forwarded-proto", "https")]);
+        let (client, scheme, host) = proxies("10.0.0.0/8").resolve(ip("203.0.113.1"), &h);
+        assert_eq!((client, scheme.as_str(), host), (ip("203.0.113.1"), "http", None));
+    }
+
+    #[test]
+    fn walks_hops_right_to_left_up_to_the_first_untrusted() {
+        //the client put a spoofed address in front; the proxy appended the real one
+        let h = headers(&[("x-forwarded-for", "198.51.100.9, 192.0.2.7")]);
+        let (client, _, _) = proxies("10.0.0.0/8").resolve(ip("10.0.0.1"), &h);
+        assert_eq!(client, ip("192.0.2.7"));
+
+        //two trusted proxies in a row are both skipped
+        let h = headers(&[("x-forwarded-for", "198.51.100.9, 192.0.2.7, 10.0.0.2")]);
+        let (client, _, _) = proxies("10.0.0.0/8").resolve(ip("10.0.0.1"), &h);
+        assert_eq!(client, ip("192.0.2.7"));
+
+        //an obfuscated hop stops the walk at the last address known
+        let h = headers(&[("x-forwarded-for", "192.0.2.7, unknown")]);
+        let (client, _, _) = proxies("10.0.0.0/8").resolve(ip("10.0.0.1"), &h);
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn trusts_ipv6_ranges_and_mapped_peers() {
+        let h = headers(&[("x-forwarded-for", "[2001:db8:1::5]:4711")]);
+        let (client, _, _) = proxies("2001:db8::/32").resolve(ip("2001:db8::1"), &h);
+        assert_eq!(client, ip("2001:db8:1::5"));
+
+        let h = headers(&[("x-forwarded-for", "192.0.2.7")]);
+        let (client, _, _) = proxies("10.0.0.0/8").resolve(ip("::ffff:10.0.0.1"), &h);
+        assert_eq!(client, ip("192.0.2.7"));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence() {
+        let h = headers(&[
+            ("forwarded", "for=198.51.100.9, for=\"[2001:db8::7]:4711\";proto=https;host=files.example.com"),
+            ("x-forwarded-for", "192.0.2.7"),
+        ]);
+        let (client, scheme, host) = proxies("10.0.0.0/8").resolve(ip("10.0.0.1"), &h);
+        assert_eq!(client, ip("2001:db8::7"));
+        assert_eq!(scheme, "https");
+        assert_eq!(host.as_deref(), Some("files.example.com"));
+    }
+
+    #[test]
+    fn shorter_proto_and_host_lists_belong_to_the_nearest_hops() {
+        let h = headers(&[
+            ("x-forwarded-for", "198.51.100.9, 192.0.2.7"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "files.example.com"),
+        ]);
+        let (client, scheme, host) = proxies("10.0.0.0/8").resolve(ip("10.0.0.1"), &h);
+        assert_eq!(client, ip("192.0.2.7"));
+        assert_eq!(scheme, "https");
+        assert_eq!(host.as_deref(), Some("files.example.com"));
+
+        //the client's own proto is never reached, and schemes other than http(s) are ignored
+        let h = headers(&[("x-forwarded-for", "198.51.100.9, 192.0.2.7"), ("x-forwarded-proto", "https, ftp")]);
+        let (_, scheme, _) = proxies("10.0.0.0/8").resolve(ip("10.0.0.1"), &h);
+        assert_eq!(scheme, "http");
+    }
+}
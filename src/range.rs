@@ -0,0 +1,129 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, Response},
+};
+use log::info;
+use std::fs::Metadata;
+
+use crate::etag;
+
+/// Outcome of checking a `Range` header against a file of known length.
+/// Only single byte ranges are honored; anything else is served in full,
+/// which RFC 9110 allows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum RangeRequest {
+    Full,
+    /// Inclusive byte offsets.
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+/// The range to serve for a request, ignoring `Range` when an `If-Range`
+/// validator no longer matches the file, so a resumed download is restarted
+/// instead of being stitched together from two versions.
+pub(crate) fn requested(headers: &HeaderMap, etag: Option<&str>, meta: &Metadata) -> RangeRequest {
+    if let Some(if_range) = headers.get(header::IF_RANGE) {
+        if headers.contains_key(header::RANGE) && !etag::if_range(if_range, etag, meta) {
+            info!("If-Range does not match, sending the full file");
+            return RangeRequest::Full;
+        }
+    }
+    parse(headers.get(header::RANGE), meta.len())
+}
+
+fn parse(header: Option<&HeaderValue>, len: u64) -> RangeRequest {
+    let Some(spec) = header
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.trim().strip_prefix("bytes="))
+    else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+    let (start, end) = match (start.trim(), end.trim()) {
+        //suffix range: the last n bytes
+        ("", n) => match n.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), len.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (s, "") => match s.parse::<u64>() {
+            Ok(s) => (s, len.saturating_sub(1)),
+            Err(_) => return RangeRequest::Full,
+        },
+        (s, e) => match (s.parse::<u64>(), e.parse::<u64>()) {
+            (Ok(s), Ok(e)) if s <= e => (s, e.min(len.saturating_sub(1))),
+            _ => return RangeRequest::Full,
+        },
+    };
+    if len == 0 || start >= len {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial { start, end }
+}
+
+pub(crate) fn not_satisfiable(len: u64) -> Response<Body> {
+    Response::builder()
+        .status(416)
+        .header(header::CONTENT_RANGE, format!("bytes */{len}"))
+        .body("Range Not Satisfiable".into())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(value: &str, len: u64) -> RangeRequest {
+        parse(Some(&HeaderValue::from_str(value).unwrap()), len)
+    }
+
+    #[test]
+    fn no_header_is_full() {
+        assert_eq!(parse(None, 10), RangeRequest::Full);
+        assert_eq!(range("items=0-1", 10), RangeRequest::Full);
+    }
+
+    #[test]
+    fn closed_and_open_ended_ranges() {
+        assert_eq!(range("bytes=0-4", 10), RangeRequest::Partial { start: 0, end: 4 });
+        assert_eq!(range("bytes=5-", 10), RangeRequest::Partial { start: 5, end: 9 });
+        assert_eq!(range(" bytes= 2 - 3 ", 10), RangeRequest::Partial { start: 2, end: 3 });
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(range("bytes=-3", 10), RangeRequest::Partial { start: 7, end: 9 });
+        //a suffix longer than the file is the whole file
+        assert_eq!(range("bytes=-30", 10), RangeRequest::Partial { start: 0, end: 9 });
+        assert_eq!(range("bytes=-0", 10), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn start_past_the_end_is_unsatisfiable() {
+        assert_eq!(range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=10-20", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=-5", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn end_past_the_end_is_clamped() {
+        assert_eq!(range("bytes=5-100", 10), RangeRequest::Partial { start: 5, end: 9 });
+        assert_eq!(range("bytes=9-9", 10), RangeRequest::Partial { start: 9, end: 9 });
+    }
+
+    #[test]
+    fn multiple_or_malformed_ranges_fall_back_to_full() {
+        assert_eq!(range("bytes=0-1,4-5", 10), RangeRequest::Full);
+        assert_eq!(range("bytes=0-1, -2", 10), RangeRequest::Full);
+        assert_eq!(range("bytes=5-2", 10), RangeRequest::Full);
+        assert_eq!(range("bytes=a-b", 10), RangeRequest::Full);
+        assert_eq!(range("bytes=-", 10), RangeRequest::Full);
+        assert_eq!(range("bytes=5", 10), RangeRequest::Full);
+    }
+}
@@ -0,0 +1,142 @@
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+/// Window of time in which a user is allowed to log in, e.g. weekdays
+/// 08:00-18:00 in Europe/Berlin. A window whose end is before its start
+/// wraps past midnight; one that starts and ends at the same time is
+/// rejected, since it's unclear whether it means never or all day.
+#[derive(Deserialize)]
+#[serde(try_from = "RawSchedule")]
+pub(crate) struct Schedule {
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    end: NaiveTime,
+    timezone: Tz,
+}
+
+#[derive(Deserialize)]
+struct RawSchedule {
+    #[serde(default)]
+    days: Vec<String>,
+    start: String,
+    end: String,
+    #[serde(default = "default_timezone")]
+    timezone: String,
+}
+
+fn default_timezone() -> String {
+    String::from("UTC")
+}
+
+impl TryFrom<RawSchedule> for Schedule {
+    type Error = String;
+
+    fn try_from(raw: RawSchedule) -> Result<Self, Self::Error> {
+        let mut days = vec![];
+        for d in raw.days {
+            days.push(
+                d.parse::<Weekday>()
+                    .map_err(|_| format!("invalid day in schedule: {d}"))?,
+            );
+        }
+        let start = NaiveTime::parse_from_str(&raw.start, "%H:%M")
+            .map_err(|e| format!("invalid schedule start {}: {e}", raw.start))?;
+        let end = NaiveTime::parse_from_str(&raw.end, "%H:%M")
+            .map_err(|e| format!("invalid schedule end {}: {e}", raw.end))?;
+        if start == end {
+            return Err(format!("invalid schedule: starts and ends at {} (leave the schedule out to allow any time)", raw.start));
+        }
+        let timezone = raw
+            .timezone
+            .parse::<Tz>()
+            .map_err(|e| format!("invalid schedule timezone {}: {e}", raw.timezone))?;
+        Ok(Schedule {
+            days,
+            start,
+            end,
+            timezone,
+        })
+    }
+}
+
+impl Schedule {
+    pub(crate) fn allows_now(&self) -> bool {
+        self.allows(Utc::now())
+    }
+
+    fn allows(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        //no days listed means every day
+        let listed = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let time = local.time();
+        if self.start <= self.end {
+            listed(local.weekday()) && time >= self.start && time < self.end
+        } else if time >= self.start {
+            listed(local.weekday())
+        } else {
+            //past midnight, an overnight window belongs to the day it started on
+            time < self.end && listed(local.weekday().pred())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn try_schedule(days: &[&str], start: &str, end: &str) -> Result<Schedule, String> {
+        Schedule::try_from(RawSchedule {
+            days: days.iter().map(|d| String::from(*d)).collect(),
+            start: String::from(start),
+            end: String::from(end),
+            timezone: default_timezone(),
+        })
+    }
+
+    fn schedule(days: &[&str], start: &str, end: &str) -> Schedule {
+        try_schedule(days, start, end).unwrap()
+    }
+
+    //2024-01-05 is a Friday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn daytime_window() {
+        let s = schedule(&["Mon", "Tue", "Wed", "Thu", "Fri"], "08:00", "18:00");
+        assert!(s.allows(at(5, 8, 0)));
+        assert!(!s.allows(at(5, 18, 0)));
+        assert!(!s.allows(at(5, 7, 59)));
+        assert!(!s.allows(at(6, 12, 0)));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_its_start_day() {
+        let s = schedule(&["Mon", "Tue", "Wed", "Thu", "Fri"], "22:00", "06:00");
+        //Friday's shift runs into Saturday morning
+        assert!(s.allows(at(5, 23, 0)));
+        assert!(s.allows(at(6, 1, 0)));
+        assert!(!s.allows(at(6, 6, 0)));
+        //no shift starts on Saturday, or ends on Monday morning
+        assert!(!s.allows(at(6, 23, 0)));
+        assert!(!s.allows(at(8, 1, 0)));
+        assert!(s.allows(at(9, 1, 0)));
+    }
+
+    #[test]
+    fn no_days_means_every_day() {
+        let s = schedule(&[], "22:00", "06:00");
+        assert!(s.allows(at(7, 23, 30)));
+        assert!(s.allows(at(8, 5, 59)));
+        assert!(!s.allows(at(8, 12, 0)));
+    }
+
+    #[test]
+    fn empty_window_is_rejected() {
+        assert!(try_schedule(&["Mon"], "08:00", "08:00").is_err());
+        assert!(try_schedule(&[], "00:00", "00:00").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderName, HeaderValue};
+use axum::{middleware::Next, response::Response};
+use std::sync::Arc;
+
+/// Policy for generated HTML pages when CONTENT_SECURITY_POLICY is unset: no
+/// scripts at all, since HEADER.html and README.md end up in listings verbatim,
+/// but same-origin images and media so READMEs and previews still render.
+const DEFAULT_CSP: &str = "default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'self' 'unsafe-inline'";
+const DEFAULT_REFERRER_POLICY: &str = "no-referrer";
+const DEFAULT_FRAME_OPTIONS: &str = "DENY";
+
+/// Hardening headers added to every response (CSP only to HTML pages), enabled
+/// with SECURITY_HEADERS. Each value can be overridden, or set empty to leave
+/// that header out. Headers a handler or RESPONSE_HEADERS_PATH sets win.
+pub(crate) struct SecurityHeaders {
+    all: Vec<(HeaderName, HeaderValue)>,
+    csp: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    pub(crate) fn from_env(
+        enabled: Option<String>,
+        csp: Option<String>,
+        referrer_policy: Option<String>,
+        frame_options: Option<String>,
+    ) -> Result<Option<SecurityHeaders>, String> {
+        if enabled.as_deref() != Some("true") {
+            return Ok(None);
+        }
+        let value = |name: &str, v: Option<String>, default: &str| -> Result<Option<HeaderValue>, String> {
+            let v = v.unwrap_or_else(|| String::from(default));
+            if v.is_empty() {
+                return Ok(None);
+            }
+            HeaderValue::from_str(&v).map(Some).map_err(|_| format!("{name}: invalid header value {v}"))
+        };
+        let mut all = vec![(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"))];
+        if let Some(v) = value("REFERRER_POLICY", referrer_policy, DEFAULT_REFERRER_POLICY)? {
+            all.push((header::REFERRER_POLICY, v));
+        }
+        if let Some(v) = value("FRAME_OPTIONS", frame_options, DEFAULT_FRAME_OPTIONS)? {
+            all.push((header::X_FRAME_OPTIONS, v));
+        }
+        Ok(Some(SecurityHeaders {
+            all,
+            csp: value("CONTENT_SECURITY_POLICY", csp, DEFAULT_CSP)?,
+        }))
+    }
+}
+
+pub(crate) async fn apply(State(security): State<Arc<SecurityHeaders>>, req: Request, next: Next) -> Response {
+    let mut response = next.run(req).await;
+    //files are served as attachments, so only the server's own pages are html here
+    let html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .is_some_and(|t| t.starts_with("text/html"));
+    let headers = response.headers_mut();
+    for (name, value) in &security.all {
+        headers.entry(name).or_insert_with(|| value.clone());
+    }
+    if let (true, Some(csp)) = (html, &security.csp) {
+        headers.entry(header::CONTENT_SECURITY_POLICY).or_insert_with(|| csp.clone());
+    }
+    response
+}
@@ -0,0 +1,66 @@
+use axum_server::{Address, Handle};
+use log::info;
+use std::{sync::Arc, time::Duration};
+
+use crate::transfers::Transfers;
+
+/// How long in-flight requests may finish after SIGTERM/SIGINT when
+/// SHUTDOWN_GRACE_PERIOD is unset.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// How often the drain progress is logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) fn grace_period(value: Option<String>) -> Duration {
+    value
+        .map(|v| Duration::from_secs(v.parse().expect("Invalid Env var: SHUTDOWN_GRACE_PERIOD")))
+        .unwrap_or(DEFAULT_GRACE_PERIOD)
+}
+
+/// A server handle that stops accepting connections on SIGTERM or SIGINT and
+/// lets open ones finish for up to `grace`, so stopping a container doesn't cut every download;
+/// whatever is still running afterwards is closed.
+pub(crate) fn handle<A>(grace: Duration, transfers: Arc<Transfers>) -> Handle<A>
+where
+    A: Address + Send + Sync + 'static,
+    Handle<A>: Send,
+{
+    let handle = Handle::new();
+    let server = handle.clone();
+    tokio::spawn(async move {
+        signal().await;
+        info!(
+            "Shutting down: draining {} connection(s), {} transfer(s) for up to {}s",
+            handle.connection_count(),
+            transfers.list().len(),
+            grace.as_secs()
+        );
+        handle.graceful_shutdown(Some(grace));
+        let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let connections = handle.connection_count();
+            if connections == 0 {
+                break;
+            }
+            info!("Draining: {connections} connection(s), {} transfer(s) left", transfers.list().len());
+        }
+    });
+    server
+}
+
+#[cfg(unix)]
+async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut term = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = term.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
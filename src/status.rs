@@ -0,0 +1,144 @@
+use axum::extract::{Request, State};
+use axum::{middleware::Next, response::Response};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use crate::feed::xml_escape;
+
+/// How many of the latest server errors the status page keeps.
+const MAX_RECENT_ERRORS: usize = 20;
+
+/// Counts the open connections, from the server handle.
+type ConnectionCount = Box<dyn Fn() -> usize + Send + Sync>;
+
+/// Process-wide facts for `/admin/status`: when the server started (users
+/// and rules are only loaded then), how many connections are open and the
+/// last few 5xx responses.
+pub(crate) struct Status {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    errors: Mutex<VecDeque<RecentError>>,
+    connections: OnceLock<ConnectionCount>,
+}
+
+#[derive(Serialize, Clone)]
+struct RecentError {
+    at: String,
+    status: u16,
+    method: String,
+    path: String,
+}
+
+/// Hit and miss counts of one of the server's caches.
+#[derive(Default)]
+pub(crate) struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    pub(crate) fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn summary(&self) -> Value {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
+        json!({
+            "hits": hits,
+            "misses": misses,
+            "hit_rate": if total > 0 { hits as f64 / total as f64 } else { 0.0 },
+        })
+    }
+}
+
+impl Status {
+    pub(crate) fn new() -> Self {
+        Status {
+            started: Instant::now(),
+            started_at: Utc::now(),
+            errors: Mutex::new(VecDeque::new()),
+            connections: OnceLock::new(),
+        }
+    }
+
+    /// Set once the server handle exists, which is after the router (and
+    /// with it this) has been built.
+    pub(crate) fn count_connections(&self, count: impl Fn() -> usize + Send + Sync + 'static) {
+        let _ = self.connections.set(Box::new(count));
+    }
+
+    pub(crate) fn summary(&self) -> Value {
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "started_at": self.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            "uptime_seconds": self.started.elapsed().as_secs(),
+            "active_connections": self.connections.get().map(|count| count()),
+            "recent_errors": *self.errors.lock().unwrap(),
+        })
+    }
+}
+
+/// Remembers every 5xx response for the status page.
+pub(crate) async fn record_errors(State(status): State<Arc<Status>>, req: Request, next: Next) -> Response {
+    let method = req.method().to_string();
+    let path = String::from(req.uri().path());
+    let response = next.run(req).await;
+    if response.status().is_server_error() {
+        let mut errors = status.errors.lock().unwrap();
+        if errors.len() >= MAX_RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(RecentError {
+            at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            status: response.status().as_u16(),
+            method,
+            path,
+        });
+    }
+    response
+}
+
+/// Renders a status summary as nested HTML tables, for a glance in a browser.
+pub(crate) fn html(value: &Value) -> String {
+    let mut r = String::from("<h1>Status</h1>\n");
+    push_value(&mut r, value);
+    r
+}
+
+fn push_value(r: &mut String, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            r.push_str("<table>\n");
+            for (key, v) in map {
+                r.push_str(&format!("<tr><th align=\"left\">{}</th><td>", xml_escape(key)));
+                push_value(r, v);
+                r.push_str("</td></tr>\n");
+            }
+            r.push_str("</table>\n");
+        }
+        Value::Array(items) if items.is_empty() => r.push_str("none"),
+        Value::Array(items) => {
+            for item in items {
+                push_value(r, item);
+            }
+        }
+        Value::String(s) => r.push_str(&xml_escape(s)),
+        Value::Null => r.push('-'),
+        other => r.push_str(&other.to_string()),
+    }
+}
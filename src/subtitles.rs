@@ -0,0 +1,43 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use std::path::{Path, PathBuf};
+
+/// For a request of `movie.vtt` that doesn't exist on disk, the `movie.srt`
+/// sidecar it can be converted from.
+pub(crate) fn srt_sidecar(file_path: &Path) -> Option<PathBuf> {
+    let ext = file_path.extension()?.to_str()?;
+    if !ext.eq_ignore_ascii_case("vtt") {
+        return None;
+    }
+    let srt = file_path.with_extension("srt");
+    if srt.is_file() {
+        Some(srt)
+    } else {
+        None
+    }
+}
+
+/// SRT and WebVTT differ mostly in the header and the decimal separator of
+/// cue timings; cue numbers are valid WebVTT cue identifiers.
+pub(crate) fn srt_to_vtt(srt: &str) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for line in srt.trim_start_matches('\u{feff}').lines() {
+        if line.contains("-->") {
+            vtt.push_str(&line.replace(',', "."));
+        } else {
+            vtt.push_str(line);
+        }
+        vtt.push('\n');
+    }
+    vtt
+}
+
+pub(crate) fn vtt_response(vtt: String) -> Response<Body> {
+    Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/vtt; charset=utf-8"))
+        .body(Body::from(vtt))
+        .unwrap()
+}
@@ -0,0 +1,85 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Response},
+};
+use mime_guess::mime;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::error::RequestError;
+use crate::listing::ListingConfig;
+use crate::{html_link, paths};
+
+fn is_image(path: &Path) -> bool {
+    mime_guess::from_path(path)
+        .first()
+        .is_some_and(|m| m.type_() == mime::IMAGE)
+}
+
+/// Capture date (YYYY-MM-DD) from the EXIF DateTimeOriginal tag.
+fn capture_date(path: &Path) -> Option<String> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => {
+            let dt = exif::DateTime::from_ascii(values.first()?).ok()?;
+            Some(format!("{:04}-{:02}-{:02}", dt.year, dt.month, dt.day))
+        }
+        _ => None,
+    }
+}
+
+/// Virtual "by date" listing of the images in a directory, grouped by EXIF
+/// capture date. Images without a usable date are listed last, and images that
+/// `.listing.toml` hides or that aren't `visible` to the user not at all.
+pub(crate) fn handle_timeline(
+    file_path: PathBuf,
+    base_dir: &Path,
+    base_path: &str,
+    visible: &dyn Fn(&Path) -> bool,
+) -> Result<Response<Body>, RequestError> {
+    let mut images = vec![];
+    for entry in file_path.read_dir()?.flatten() {
+        let path = entry.path();
+        if path.is_file() && is_image(&path) && visible(&path) {
+            images.push(path);
+        }
+    }
+    let mut by_date: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let mut undated = vec![];
+    for path in ListingConfig::load(&file_path).arrange(images) {
+        match capture_date(&path) {
+            Some(date) => by_date.entry(date).or_default().push(path),
+            None => undated.push(path),
+        }
+    }
+
+    let mut r = String::new();
+    let dir = paths::relativize(&file_path, base_dir);
+    if let Some(parent) = dir.parent() {
+        r.push_str(html_link(base_path, parent).as_str());
+        r.push_str("<br>\n");
+    }
+    let groups = by_date
+        .into_iter()
+        .chain((!undated.is_empty()).then(|| (String::from("Unknown date"), undated)));
+    for (date, mut images) in groups {
+        images.sort();
+        r.push_str(&format!("<h3>{date}</h3>\n"));
+        for image in images {
+            let p = paths::relativize(&image, base_dir);
+            r.push_str(html_link(base_path, &p).as_str());
+            r.push_str("<br>\n");
+        }
+    }
+    Ok(Response::builder()
+        .status(200)
+        .header(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))
+        .body(Body::from(r))
+        .unwrap())
+}
@@ -0,0 +1,83 @@
+use axum::http::StatusCode;
+use axum_server::{accept::Accept, Address, Server};
+use hyper_util::rt::TokioTimer;
+use std::{pin::Pin, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_io_timeout::TimeoutStream;
+use tower_http::timeout::TimeoutLayer;
+
+/// Limits that reclaim connections from stuck clients and slow disks. Each is
+/// given in seconds; `0` disables one.
+///
+/// - header read: time for a client to finish a request's headers once it
+///   has started sending, and for a keep-alive connection to sit idle
+///   between requests (30s default)
+/// - idle: time a connection may make no progress writing a response, e.g. a
+///   client that stopped reading mid-download (off by default)
+/// - request: time to produce a response, answered with 408 if exceeded;
+///   streaming a download's body afterwards isn't counted (off by default)
+pub(crate) struct Timeouts {
+    header_read: Option<Duration>,
+    idle: Option<Duration>,
+    request: Option<Duration>,
+}
+
+const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn seconds(value: Option<String>, name: &str) -> Option<Option<Duration>> {
+    value.map(|v| match v.parse::<u64>().unwrap_or_else(|_| panic!("Invalid Env var: {name}")) {
+        0 => None,
+        s => Some(Duration::from_secs(s)),
+    })
+}
+
+impl Timeouts {
+    pub(crate) fn from_env(header_read: Option<String>, idle: Option<String>, request: Option<String>) -> Self {
+        Timeouts {
+            header_read: seconds(header_read, "HEADER_READ_TIMEOUT").unwrap_or(Some(DEFAULT_HEADER_READ_TIMEOUT)),
+            idle: seconds(idle, "IDLE_TIMEOUT").flatten(),
+            request: seconds(request, "REQUEST_TIMEOUT").flatten(),
+        }
+    }
+
+    pub(crate) fn layer(&self) -> Option<TimeoutLayer> {
+        self.request
+            .map(|t| TimeoutLayer::with_status_code(StatusCode::REQUEST_TIMEOUT, t))
+    }
+
+    /// Applies the header read and idle timeouts to a server's connections.
+    pub(crate) fn apply<A: Address, Acc>(&self, mut server: Server<A, Acc>) -> Server<A, IdleAcceptor<Acc>> {
+        server
+            .http_builder()
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(self.header_read);
+        let idle = self.idle;
+        server.map(|inner| IdleAcceptor { inner, idle })
+    }
+}
+
+/// Wraps each accepted stream so a write that makes no progress for the idle
+/// timeout fails and the connection is dropped. Runs before TLS, so stalled
+/// handshakes are covered too.
+#[derive(Clone)]
+pub(crate) struct IdleAcceptor<A> {
+    inner: A,
+    idle: Option<Duration>,
+}
+
+impl<A, I, S> Accept<I, S> for IdleAcceptor<A>
+where
+    A: Accept<Pin<Box<TimeoutStream<I>>>, S>,
+    I: AsyncRead + AsyncWrite,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = A::Future;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let mut stream = TimeoutStream::new(stream);
+        stream.set_write_timeout(self.idle);
+        self.inner.accept(Box::pin(stream), service)
+    }
+}
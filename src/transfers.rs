@@ -0,0 +1,240 @@
+use axum::body::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::Poll,
+    time::Instant,
+};
+
+use crate::auth::AuthenticatedUser;
+use crate::usage::{self, Usage};
+
+/// Suggested wait before retrying once a concurrency cap is hit.
+const RETRY_AFTER_SECONDS: u64 = 10;
+
+/// Registry of in-flight downloads, so admins can see who is pulling what and stop it.
+/// Also enforces the global and per-user caps on simultaneous transfers.
+pub(crate) struct Transfers {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<u64, Arc<Transfer>>>,
+    max_total: Option<usize>,
+    max_per_user: Option<usize>,
+    usage: Arc<Usage>,
+}
+
+pub(crate) enum LimitReached {
+    Global,
+    User,
+    Monthly,
+}
+
+impl LimitReached {
+    pub(crate) fn retry_after(&self) -> u64 {
+        match self {
+            LimitReached::Monthly => usage::seconds_until_next_month(),
+            _ => RETRY_AFTER_SECONDS,
+        }
+    }
+}
+
+impl fmt::Display for LimitReached {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitReached::Global => write!(f, "global transfer limit reached"),
+            LimitReached::User => write!(f, "per-user transfer limit reached"),
+            LimitReached::Monthly => write!(f, "monthly transfer volume reached"),
+        }
+    }
+}
+
+struct Transfer {
+    id: u64,
+    username: String,
+    path: String,
+    client_ip: IpAddr,
+    started: Instant,
+    bytes: AtomicU64,
+    cancelled: AtomicBool,
+    /// Set once the body has been streamed to the end; anything else when the
+    /// guard drops means the client went away or the stream failed.
+    completed: AtomicBool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct TransferSummary {
+    id: u64,
+    user: String,
+    path: String,
+    client_ip: String,
+    bytes: u64,
+    seconds: f64,
+    bytes_per_second: u64,
+}
+
+impl Transfers {
+    pub(crate) fn new(max_total: Option<usize>, max_per_user: Option<usize>, usage: Arc<Usage>) -> Self {
+        Transfers {
+            next_id: AtomicU64::new(0),
+            active: Mutex::new(HashMap::new()),
+            max_total,
+            max_per_user,
+            usage,
+        }
+    }
+
+    pub(crate) fn max_total(&self) -> Option<usize> {
+        self.max_total
+    }
+
+    pub(crate) fn max_per_user(&self) -> Option<usize> {
+        self.max_per_user
+    }
+
+    /// Registers a new transfer unless that would exceed a cap. The user's own
+    /// `max_transfers` overrides the server-wide per-user cap. Transfers made
+    /// while impersonating are accounted to the admin, so the target's caps
+    /// and usage are left alone.
+    pub(crate) fn start(
+        self: &Arc<Self>,
+        user: &AuthenticatedUser,
+        path: &str,
+        client_ip: IpAddr,
+    ) -> Result<TransferGuard, LimitReached> {
+        let (username, monthly_limit, max_transfers) = match &user.impersonated_by {
+            Some(admin) => (admin.as_str(), None, None),
+            None => (user.username.as_str(), user.monthly_transfer_limit, user.max_transfers),
+        };
+        if let Some(max) = monthly_limit {
+            if self.usage.current(username) >= max {
+                return Err(LimitReached::Monthly);
+            }
+        }
+        let mut active = self.active.lock().unwrap();
+        if let Some(max) = self.max_total {
+            if active.len() >= max {
+                return Err(LimitReached::Global);
+            }
+        }
+        if let Some(max) = max_transfers.or(self.max_per_user) {
+            if active.values().filter(|t| t.username == username).count() >= max {
+                return Err(LimitReached::User);
+            }
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let transfer = Arc::new(Transfer {
+            id,
+            username: String::from(username),
+            path: String::from(path),
+            client_ip,
+            started: Instant::now(),
+            bytes: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            completed: AtomicBool::new(false),
+        });
+        active.insert(id, transfer.clone());
+        Ok(TransferGuard {
+            transfers: self.clone(),
+            transfer,
+        })
+    }
+
+    pub(crate) fn list(&self) -> Vec<TransferSummary> {
+        let mut summaries: Vec<TransferSummary> = self
+            .active
+            .lock()
+            .unwrap()
+            .values()
+            .map(|t| {
+                let bytes = t.bytes.load(Ordering::Relaxed);
+                let seconds = t.started.elapsed().as_secs_f64();
+                TransferSummary {
+                    id: t.id,
+                    user: t.username.clone(),
+                    path: t.path.clone(),
+                    client_ip: t.client_ip.to_string(),
+                    bytes,
+                    seconds,
+                    bytes_per_second: if seconds > 0.0 { (bytes as f64 / seconds) as u64 } else { 0 },
+                }
+            })
+            .collect();
+        summaries.sort_by_key(|s| s.id);
+        summaries
+    }
+
+    /// Flags a transfer as cancelled; its stream errors out on the next chunk.
+    pub(crate) fn cancel(&self, id: u64) -> bool {
+        match self.active.lock().unwrap().get(&id) {
+            Some(t) => {
+                t.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keeps a transfer listed for as long as its response body is alive.
+pub(crate) struct TransferGuard {
+    transfers: Arc<Transfers>,
+    transfer: Arc<Transfer>,
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        self.transfers.active.lock().unwrap().remove(&self.transfer.id);
+        let t = &self.transfer;
+        let bytes = t.bytes.load(Ordering::Relaxed);
+        let seconds = t.started.elapsed().as_secs_f64();
+        if t.completed.load(Ordering::Relaxed) {
+            info!("Transfer {} of {} to {} finished: {bytes} bytes in {seconds:.1}s", t.id, t.path, t.username);
+        } else if t.cancelled.load(Ordering::Relaxed) {
+            warn!("Transfer {} of {} to {} cancelled by admin after {bytes} bytes", t.id, t.path, t.username);
+        } else {
+            //499 as nginx logs it: the client closed the connection before the body was sent
+            warn!("499 Transfer {} of {} to {} aborted after {bytes} bytes in {seconds:.1}s", t.id, t.path, t.username);
+        }
+        self.transfers.usage.record(&t.username, bytes);
+    }
+}
+
+/// Counts the bytes of a response body against its transfer. `len` is the
+/// body's Content-Length, if it has one: hyper stops polling a sized body once
+/// that many bytes are out, so reaching it is what marks the transfer complete,
+/// and a body that ends before then is an aborted transfer.
+pub(crate) fn track<S>(stream: S, guard: TransferGuard, len: Option<u64>) -> impl Stream<Item = io::Result<Bytes>>
+where
+    S: Stream<Item = io::Result<Bytes>>,
+{
+    let transfer = guard.transfer.clone();
+    //the guard lives in the trailing stream: Chain drops `stream` before polling it
+    let end = stream::poll_fn(move |_| {
+        let t = &guard.transfer;
+        if len.is_none_or(|len| t.bytes.load(Ordering::Relaxed) >= len) {
+            t.completed.store(true, Ordering::Relaxed);
+        }
+        Poll::Ready(None)
+    });
+    stream
+        .map(move |chunk| {
+            if transfer.cancelled.load(Ordering::Relaxed) {
+                return Err(io::Error::other("transfer cancelled by admin"));
+            }
+            if let Ok(bytes) = &chunk {
+                let sent = transfer.bytes.fetch_add(bytes.len() as u64, Ordering::Relaxed) + bytes.len() as u64;
+                if Some(sent) == len {
+                    transfer.completed.store(true, Ordering::Relaxed);
+                }
+            }
+            chunk
+        })
+        .chain(end)
+}
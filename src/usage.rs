@@ -0,0 +1,118 @@
+use chrono::{Datelike, TimeZone, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Bytes sent to each user in the current calendar month (UTC). Counters
+/// reset lazily the first time a user is seen in a new month.
+pub(crate) struct Usage {
+    path: Option<PathBuf>,
+    months: Mutex<HashMap<String, MonthlyUsage>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct MonthlyUsage {
+    month: String,
+    bytes: u64,
+}
+
+fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+/// Seconds until the monthly counters roll over.
+pub(crate) fn seconds_until_next_month() -> u64 {
+    let now = Utc::now();
+    let (year, month) = if now.month() == 12 {
+        (now.year() + 1, 1)
+    } else {
+        (now.year(), now.month() + 1)
+    };
+    let next = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+    (next - now).num_seconds().max(0) as u64
+}
+
+fn read(path: &Path) -> Result<HashMap<String, MonthlyUsage>, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    serde_json::from_str(&data).map_err(|e| format!("{}: invalid JSON: {e}", path.display()))
+}
+
+/// Problems with a persisted usage file, reported by `config::check` so that
+/// `load` never has to fail. A file that doesn't exist yet is fine.
+pub(crate) fn check(path: &Path) -> Option<String> {
+    if !path.exists() {
+        return None;
+    }
+    read(path).err()
+}
+
+impl Usage {
+    /// Loads previously persisted counters from `path`, if given and present.
+    /// An unreadable file (already reported by `check`) starts the counters
+    /// from zero.
+    pub(crate) fn load(path: Option<PathBuf>) -> Self {
+        let months = match &path {
+            Some(p) if p.exists() => read(p).unwrap_or_else(|e| {
+                warn!("USAGE_JSON_PATH: {e}");
+                HashMap::new()
+            }),
+            _ => HashMap::new(),
+        };
+        Usage {
+            path,
+            months: Mutex::new(months),
+        }
+    }
+
+    pub(crate) fn record(&self, username: &str, bytes: u64) {
+        let month = current_month();
+        let mut months = self.months.lock().unwrap();
+        let usage = months.entry(String::from(username)).or_insert(MonthlyUsage {
+            month: month.clone(),
+            bytes: 0,
+        });
+        if usage.month != month {
+            usage.month = month;
+            usage.bytes = 0;
+        }
+        usage.bytes += bytes;
+    }
+
+    pub(crate) fn current(&self, username: &str) -> u64 {
+        let month = current_month();
+        match self.months.lock().unwrap().get(username) {
+            Some(usage) if usage.month == month => usage.bytes,
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> HashMap<String, MonthlyUsage> {
+        let month = current_month();
+        self.months
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, u)| u.month == month)
+            .map(|(user, u)| (user.clone(), u.clone()))
+            .collect()
+    }
+
+    /// Writes the counters to disk via a temporary file, so a crash mid-write
+    /// never leaves a truncated file behind.
+    pub(crate) fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let data = serde_json::to_string(&*self.months.lock().unwrap()).unwrap();
+        let tmp = path.with_extension("tmp");
+        match fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, path)) {
+            Ok(_) => debug!("Saved usage to {}", path.display()),
+            Err(e) => warn!("Failed to save usage to {}: {e}", path.display()),
+        }
+    }
+}